@@ -0,0 +1,89 @@
+use super::{FractalPendulumApp, spherical};
+
+// 每隔这么多帧按平均耗时调整一次深度
+const INTERVAL: u32 = 10;
+// 递归深度的上限，导入的设置里更深也按这个算，线段数2^(depth+1)还装得进u64
+const MAX_DEPTH: usize = 40;
+
+// 自动选择的递归深度，以及这一轮累计的帧数和耗时
+pub(super) struct AdaptiveData {
+    pub(super) depth: usize,
+    frames: u32,
+    total: f32,
+}
+
+impl Default for AdaptiveData {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            frames: 0,
+            total: 0.0,
+        }
+    }
+}
+
+impl FractalPendulumApp {
+    // 当前模式下递归深度设置的上限，三维模式不剪枝，每层都全部展开
+    pub(super) fn max_depth(&self) -> usize {
+        if self.setting.spherical {
+            spherical::MAX_DEPTH
+        } else {
+            MAX_DEPTH
+        }
+    }
+
+    // 实际绘制用的递归深度，自适应时递归深度设置是上限
+    pub(super) fn render_depth(&self) -> usize {
+        let depth = self.setting.depth.min(self.max_depth());
+        if self.setting.adaptive_depth {
+            self.data.adaptive.depth.min(depth)
+        } else {
+            depth
+        }
+    }
+
+    // 每多一层线段数最多翻一倍，所以耗时不到目标一半时才加深；远超目标时一次减去好几层，免得卡很久。
+    // 暂停时画面不动，不必顾及帧率，逐帧加深到上限
+    pub(super) fn adapt_depth(&mut self, ctx: &egui::Context) {
+        let max_depth = self.setting.depth.min(self.max_depth());
+        let data = &mut self.data.adaptive;
+        if !self.setting.adaptive_depth {
+            data.depth = max_depth;
+            return;
+        }
+
+        if self.data.paused {
+            data.frames = 0;
+            data.total = 0.0;
+            if data.depth < max_depth {
+                data.depth += 1;
+                ctx.request_repaint();
+            }
+            return;
+        }
+
+        let target = self.setting.target_frame_time;
+        let frame_time = self.data.frame_time as f32;
+        if frame_time > 2.0 * target {
+            let excess = (frame_time / target).log2().floor() as usize;
+            data.depth = data.depth.saturating_sub(excess).max(1);
+            data.frames = 0;
+            data.total = 0.0;
+            return;
+        }
+
+        data.frames += 1;
+        data.total += frame_time;
+        if data.frames >= INTERVAL {
+            let average = data.total / data.frames as f32;
+            if average > target {
+                data.depth = data.depth.saturating_sub(1).max(1);
+            } else if 2.0 * average < target {
+                data.depth += 1;
+            }
+            data.depth = data.depth.min(max_depth);
+            data.frames = 0;
+            data.total = 0.0;
+        }
+    }
+}
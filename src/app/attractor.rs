@@ -0,0 +1,216 @@
+use egui::{Color32, Pos2, Rect};
+use num_complex::Complex64;
+use rand::{Rng as _, SeedableRng as _, rngs::StdRng};
+
+use super::{FractalPendulumApp, hsl_to_rgb, lerp, tree};
+
+// 丢掉开头这么多步，让点先落到吸引子上
+const BURN_IN: usize = 32;
+
+// 每帧最多撒的点数，点数多时分几帧撒完，运行中每帧都从头撒这么多
+const BUDGET: usize = 1 << 18;
+
+// 点云依赖的全部输入，都没变时接着上次撒到的地方往下撒
+#[derive(PartialEq)]
+struct Key {
+    transforms: [Complex64; 2],
+    // 第一根臂的末端和第一根臂，点云以它们为原点和单位
+    origin: Complex64,
+    unit: Complex64,
+    view: tree::View,
+    hue: [f32; 2],
+    saturation: f32,
+    luminance: f32,
+    points: usize,
+    rect: Rect,
+    pixels_per_point: f32,
+}
+
+// 混沌游戏的当前状态，种子固定，输入不变时每次撒出来一样，不会闪
+struct Walk {
+    rng: StdRng,
+    s: Complex64,
+    // 最近8次所用变换的编号
+    address: u8,
+    bits: u64,
+    step: usize,
+}
+
+impl Walk {
+    fn new() -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(0),
+            s: Complex64::new(0.0, 0.0),
+            address: 0,
+            bits: 0,
+            step: 0,
+        }
+    }
+}
+
+// 树叶收敛到两个相似变换的吸引子上。以第一根臂末端为原点、第一根臂为单位，
+// 末梢的位置是s = t_i(1 + t_j(1 + …))，所以吸引子由f_i(s) = t_i(1 + s)生成。
+// 用随机迭代（混沌游戏）撒点，统计每个像素落了多少点，按对数密度显示
+pub(super) struct AttractorData {
+    key: Option<Key>,
+    walk: Walk,
+    size: [usize; 2],
+    count: Vec<u32>,
+    // 落在每个像素上的点的颜色之和
+    color: Vec<[f32; 3]>,
+    texture: Option<egui::TextureHandle>,
+}
+
+impl Default for AttractorData {
+    fn default() -> Self {
+        Self {
+            key: None,
+            walk: Walk::new(),
+            size: [0; 2],
+            count: Vec::new(),
+            color: Vec::new(),
+            texture: None,
+        }
+    }
+}
+
+impl AttractorData {
+    fn clear(&mut self, key: Key, size: [usize; 2]) {
+        self.key = Some(key);
+        self.walk = Walk::new();
+        self.size = size;
+        self.count.clear();
+        self.count.resize(size[0] * size[1], 0);
+        self.color.clear();
+        self.color.resize(size[0] * size[1], [0.0; 3]);
+    }
+
+    // 接着往下撒点，直到总步数为end
+    fn scatter(&mut self, end: usize) {
+        let Some(key) = &self.key else {
+            return;
+        };
+        let [t1, t2] = key.transforms;
+        let palette: Vec<[f32; 3]> = (0..=u8::MAX)
+            .map(|address| {
+                let [r, g, b, _] = hsl_to_rgb(
+                    lerp(key.hue[0], key.hue[1], (f32::from(address) + 0.5) / 256.0),
+                    key.saturation,
+                    key.luminance,
+                )
+                .to_normalized_gamma_f32();
+                [r, g, b]
+            })
+            .collect();
+
+        let [width, height] = self.size;
+        let walk = &mut self.walk;
+        while walk.step < end {
+            if walk.step % 64 == 0 {
+                walk.bits = walk.rng.random();
+            }
+            let branch = walk.bits & 1;
+            walk.bits >>= 1;
+            walk.s = if branch == 0 { t1 } else { t2 } * (1.0 + walk.s);
+            walk.address = (walk.address >> 1) | ((branch as u8) << 7);
+            walk.step += 1;
+            if walk.step <= BURN_IN {
+                continue;
+            }
+
+            let p = (key.view.apply(key.origin + key.unit * walk.s) - key.rect.min)
+                * key.pixels_per_point;
+            if p.x >= 0.0 && p.y >= 0.0 && (p.x as usize) < width && (p.y as usize) < height {
+                let index = p.y as usize * width + p.x as usize;
+                self.count[index] += 1;
+                for (c, p) in self.color[index]
+                    .iter_mut()
+                    .zip(palette[usize::from(walk.address)])
+                {
+                    *c += p;
+                }
+            }
+        }
+    }
+
+    // 平均颜色乘上对数密度，没有点的地方透明
+    fn image(&self) -> egui::ColorImage {
+        let max = self.count.iter().copied().max().unwrap_or(0);
+        let norm = 1.0 / (max as f32).ln_1p().max(f32::MIN_POSITIVE);
+        let pixels = self
+            .count
+            .iter()
+            .zip(&self.color)
+            .map(|(&count, color)| {
+                if count == 0 {
+                    return Color32::TRANSPARENT;
+                }
+                let intensity = (count as f32).ln_1p() * norm;
+                let [r, g, b] = color.map(|c| (c / count as f32 * intensity * 255.0).round() as u8);
+                Color32::from_rgba_premultiplied(r, g, b, (intensity * 255.0).round() as u8)
+            })
+            .collect();
+        egui::ColorImage::new(self.size, pixels)
+    }
+}
+
+impl FractalPendulumApp {
+    // 以吸引子点云代替线段，颜色按最近8次所用变换的编号取，与线段按路径染色一致。
+    // 每帧按预算接着撒点，输入变了才从头撒
+    pub(super) fn paint_attractor(
+        &mut self,
+        painter: &egui::Painter,
+        to_screen: &egui::emath::RectTransform,
+        hue: [f32; 2],
+    ) {
+        let rect = painter.clip_rect();
+        let pixels_per_point = painter.ctx().pixels_per_point();
+        let (grower, root) = self.grower(&self.setting.state(), hue, 0, to_screen);
+        let key = Key {
+            transforms: grower.transforms,
+            origin: root.start + root.vec,
+            unit: root.vec,
+            view: grower.view,
+            hue,
+            saturation: self.setting.saturation,
+            luminance: self.setting.luminance,
+            points: self.setting.attractor_points,
+            rect,
+            pixels_per_point,
+        };
+
+        let data = &mut self.data.attractor;
+        if data.key.as_ref() != Some(&key) {
+            let size = (rect.size() * pixels_per_point).round();
+            data.clear(key, [size.x as usize, size.y as usize]);
+        }
+
+        let total = self.setting.attractor_points + BURN_IN;
+        if data.walk.step < total {
+            data.scatter(total.min(data.walk.step + BUDGET));
+            let image = data.image();
+            match &mut data.texture {
+                Some(texture) => texture.set(image, egui::TextureOptions::LINEAR),
+                None => {
+                    data.texture = Some(painter.ctx().load_texture(
+                        "吸引子",
+                        image,
+                        egui::TextureOptions::LINEAR,
+                    ));
+                }
+            }
+            if data.walk.step < total {
+                painter.ctx().request_repaint();
+            }
+        }
+
+        if let Some(texture) = &data.texture {
+            painter.image(
+                texture.id(),
+                rect,
+                Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+                Color32::WHITE,
+            );
+        }
+    }
+}
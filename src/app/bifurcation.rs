@@ -0,0 +1,436 @@
+use std::f64::consts::PI;
+
+use egui::{Color32, Pos2, Rect, Shape, Vec2};
+
+use super::{
+    FractalPendulumApp, FractalPendulumAppSetting, Ode, State, cart::Controller,
+    modulation::PARAMETER_NAMES, pivot::PivotMode, wrap_angle,
+};
+
+// 庞加莱截面上可以记录的变量，顺序与q相同
+const VARIABLE_NAMES: [&str; 6] = ["θ1", "ω1", "θ2", "ω2", "θ3", "ω3"];
+
+// 支点简谐运动的两个方向
+const AXIS_NAMES: [&str; 2] = ["x", "y"];
+
+#[derive(PartialEq, Clone, Copy)]
+enum SweepParameter {
+    M1,
+    M2,
+    M3,
+    L1,
+    L2,
+    L3,
+    G,
+    // 支点简谐运动某个方向的振幅和频率
+    PivotAmplitude(usize),
+    PivotFrequency(usize),
+    // 某个参数的调制振幅和频率，顺序与调制的参数一致
+    ModulationAmplitude(usize),
+    ModulationFrequency(usize),
+}
+
+impl SweepParameter {
+    // 当前设置下可以扫描的参数，支点做简谐运动或者打开调制时才能扫驱动的振幅和频率
+    fn available(setting: &FractalPendulumAppSetting) -> Vec<Self> {
+        let mut parameters = vec![
+            Self::M1,
+            Self::M2,
+            Self::M3,
+            Self::L1,
+            Self::L2,
+            Self::L3,
+            Self::G,
+        ];
+        if setting.pivot_mode == PivotMode::Script {
+            parameters.extend((0..AXIS_NAMES.len()).map(Self::PivotAmplitude));
+            parameters.extend((0..AXIS_NAMES.len()).map(Self::PivotFrequency));
+        }
+        if setting.modulated {
+            parameters.extend((0..PARAMETER_NAMES.len()).map(Self::ModulationAmplitude));
+            parameters.extend((0..PARAMETER_NAMES.len()).map(Self::ModulationFrequency));
+        }
+        parameters
+    }
+
+    fn name(self) -> String {
+        match self {
+            Self::M1 => "m1".to_owned(),
+            Self::M2 => "m2".to_owned(),
+            Self::M3 => "m3".to_owned(),
+            Self::L1 => "l1".to_owned(),
+            Self::L2 => "l2".to_owned(),
+            Self::L3 => "l3".to_owned(),
+            Self::G => "g".to_owned(),
+            Self::PivotAmplitude(axis) => format!("支点{}振幅", AXIS_NAMES[axis]),
+            Self::PivotFrequency(axis) => format!("支点{}频率", AXIS_NAMES[axis]),
+            Self::ModulationAmplitude(i) => format!("{}调制振幅", PARAMETER_NAMES[i]),
+            Self::ModulationFrequency(i) => format!("{}调制频率", PARAMETER_NAMES[i]),
+        }
+    }
+
+    fn value_mut(self, setting: &mut FractalPendulumAppSetting) -> &mut f64 {
+        match self {
+            Self::M1 => &mut setting.m[0],
+            Self::M2 => &mut setting.m[1],
+            Self::M3 => &mut setting.m[2],
+            Self::L1 => &mut setting.l[0],
+            Self::L2 => &mut setting.l[1],
+            Self::L3 => &mut setting.l[2],
+            Self::G => &mut setting.g,
+            Self::PivotAmplitude(axis) => &mut setting.pivot_amplitude[axis],
+            Self::PivotFrequency(axis) => &mut setting.pivot_frequency[axis],
+            Self::ModulationAmplitude(i) => &mut setting.modulation_amplitude[i],
+            Self::ModulationFrequency(i) => &mut setting.modulation_frequency[i],
+        }
+    }
+}
+
+// 一次扫描需要的全部参数，可以整个交给后台线程
+struct Sweep {
+    setting: FractalPendulumAppSetting,
+    // 控制器沿用当前参数下设计好的，不随扫描的参数重新设计
+    controller: Option<Controller>,
+    parameter: SweepParameter,
+    min: f64,
+    max: f64,
+    steps: usize,
+    transient: f64,
+    window: f64,
+    variable: usize,
+}
+
+impl Sweep {
+    fn value(&self, i: usize) -> f64 {
+        if self.steps <= 1 {
+            self.min
+        } else {
+            self.min + (self.max - self.min) * i as f64 / (self.steps - 1) as f64
+        }
+    }
+
+    // 第i个参数值下，先跑过暂态，再接着暂态结束的时刻记录截面上的采样
+    fn column(&self, i: usize) -> (f64, Vec<f64>) {
+        let value = self.value(i);
+        let mut setting = self.setting.clone();
+        *self.parameter.value_mut(&mut setting) = value;
+
+        let mut ode = Ode::new(&setting);
+        ode.attach_controller(self.controller.as_ref());
+        let start = if self.transient > 0.0 {
+            ode.flow(setting.state(), 0.0, self.transient, setting.h)
+        } else {
+            Some(setting.state())
+        };
+        let samples = start
+            .and_then(|y| ode.trajectory(y, self.transient, self.window, setting.h))
+            .map(|trajectory| poincare_section(&trajectory, self.variable))
+            .unwrap_or_default();
+        (value, samples)
+    }
+}
+
+// 截面取θ1 = 0且ω1 > 0，相邻两步之间线性插值
+fn poincare_section(trajectory: &[State], variable: usize) -> Vec<f64> {
+    trajectory
+        .windows(2)
+        .filter_map(|pair| {
+            let (a, b) = (&pair[0], &pair[1]);
+            let (wa, wb) = (wrap_angle(a[0]), wrap_angle(b[0]));
+            (wa < 0.0 && wb >= 0.0 && wb - wa < PI && b[1] > 0.0).then(|| {
+                let s = -wa / (wb - wa);
+                let v = a[variable] + (b[variable] - a[variable]) * s;
+                if variable % 2 == 0 { wrap_angle(v) } else { v }
+            })
+        })
+        .collect()
+}
+
+// 正在进行的扫描。原生平台在后台线程里算，网页上每帧算一列
+#[cfg(not(target_arch = "wasm32"))]
+struct Job {
+    receiver: std::sync::mpsc::Receiver<(f64, Vec<f64>)>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Job {
+    fn start(sweep: Sweep) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        // 接收端被丢弃后发送失败，线程随之退出
+        std::thread::spawn(move || {
+            for i in 0..sweep.steps {
+                if sender.send(sweep.column(i)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { receiver }
+    }
+
+    fn poll(&self) -> Vec<(f64, Vec<f64>)> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+struct Job {
+    sweep: Sweep,
+    next: usize,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Job {
+    fn start(sweep: Sweep) -> Self {
+        Self { sweep, next: 0 }
+    }
+
+    fn poll(&mut self) -> Vec<(f64, Vec<f64>)> {
+        if self.next < self.sweep.steps {
+            self.next += 1;
+            vec![self.sweep.column(self.next - 1)]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+pub(super) struct BifurcationData {
+    parameter: SweepParameter,
+    min: f64,
+    max: f64,
+    steps: usize,
+    transient: f64,
+    window: f64,
+    variable: usize,
+    // 已经算出的点，横坐标为参数值
+    points: Vec<(f64, f64)>,
+    finished: usize,
+    total: usize,
+    job: Option<Job>,
+    open: bool,
+}
+
+impl Default for BifurcationData {
+    fn default() -> Self {
+        Self {
+            parameter: SweepParameter::G,
+            min: 1.0,
+            max: 20.0,
+            steps: 100,
+            transient: 10.0,
+            window: 30.0,
+            variable: 2,
+            points: Vec::new(),
+            finished: 0,
+            total: 0,
+            job: None,
+            open: false,
+        }
+    }
+}
+
+impl FractalPendulumApp {
+    pub(super) fn bifurcation_ui(&mut self, ui: &mut egui::Ui) {
+        // 按钮里要用，先取出来，后面data一直借着self
+        let controller = self.controller().cloned();
+        let parameters = SweepParameter::available(&self.setting);
+        let data = &mut self.data.bifurcation;
+        // 关掉驱动后就不能再扫它的参数了
+        if !parameters.contains(&data.parameter) {
+            data.parameter = SweepParameter::G;
+        }
+
+        egui::Grid::new("分岔图网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("扫描参数");
+                egui::ComboBox::from_id_salt("扫描参数选择")
+                    .selected_text(data.parameter.name())
+                    .show_ui(ui, |ui| {
+                        for parameter in parameters {
+                            ui.selectable_value(&mut data.parameter, parameter, parameter.name());
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("起点");
+                ui.add(egui::DragValue::new(&mut data.min).speed(0.01));
+                ui.end_row();
+
+                ui.label("终点");
+                ui.add(egui::DragValue::new(&mut data.max).speed(0.01));
+                ui.end_row();
+
+                ui.label("采样数");
+                ui.add(egui::DragValue::new(&mut data.steps).range(2..=2000));
+                ui.end_row();
+
+                ui.label("暂态时长")
+                    .on_hover_text("每个参数值下先演化这么久再开始记录");
+                ui.add(
+                    egui::DragValue::new(&mut data.transient)
+                        .speed(0.1)
+                        .range(0.0..=1000.0),
+                );
+                ui.end_row();
+
+                ui.label("记录时长");
+                ui.add(
+                    egui::DragValue::new(&mut data.window)
+                        .speed(0.1)
+                        .range(0.1..=1000.0),
+                );
+                ui.end_row();
+
+                ui.label("记录变量")
+                    .on_hover_text("截面取θ1 = 0且ω1 > 0，记录此时该变量的值");
+                egui::ComboBox::from_id_salt("记录变量选择")
+                    .selected_text(VARIABLE_NAMES[data.variable])
+                    .show_ui(ui, |ui| {
+                        for (i, name) in VARIABLE_NAMES.iter().enumerate() {
+                            ui.selectable_value(&mut data.variable, i, *name);
+                        }
+                    });
+                ui.end_row();
+            });
+
+        ui.horizontal(|ui| {
+            if ui
+                .button("开始")
+                .on_hover_text("从当前状态和参数出发，只改变扫描参数")
+                .clicked()
+            {
+                data.points.clear();
+                data.finished = 0;
+                data.total = data.steps;
+                data.job = Some(Job::start(Sweep {
+                    setting: self.setting.clone(),
+                    controller: controller.clone(),
+                    parameter: data.parameter,
+                    min: data.min,
+                    max: data.max,
+                    steps: data.steps,
+                    transient: data.transient,
+                    window: data.window,
+                    variable: data.variable,
+                }));
+                data.open = true;
+            }
+
+            if data.job.is_some() && ui.button("停止").clicked() {
+                data.job = None;
+            }
+
+            if ui.button("显示").clicked() {
+                data.open = true;
+            }
+        });
+    }
+
+    // 每帧都要调用，收集后台结果并画出分岔图窗口
+    pub(super) fn bifurcation_window(&mut self, ctx: &egui::Context) {
+        let data = &mut self.data.bifurcation;
+
+        if let Some(job) = &mut data.job {
+            for (value, samples) in job.poll() {
+                data.points
+                    .extend(samples.into_iter().map(|sample| (value, sample)));
+                data.finished += 1;
+            }
+            if data.finished >= data.total {
+                data.job = None;
+            } else {
+                ctx.request_repaint();
+            }
+        }
+
+        let mut open = data.open;
+        egui::Window::new("分岔图")
+            .open(&mut open)
+            .default_size([400.0, 300.0])
+            .show(ctx, |ui| {
+                if data.job.is_some() {
+                    ui.add(
+                        egui::ProgressBar::new(data.finished as f32 / data.total.max(1) as f32)
+                            .show_percentage(),
+                    );
+                }
+                plot(ui, &data.points, data.variable);
+            });
+        data.open = open;
+    }
+}
+
+fn plot(ui: &mut egui::Ui, points: &[(f64, f64)], variable: usize) {
+    let (response, painter) = ui.allocate_painter(
+        Vec2::new(ui.available_width(), ui.available_height().max(200.0)),
+        egui::Sense::hover(),
+    );
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+
+    let x_range = points
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &(x, _)| {
+            (lo.min(x), hi.max(x))
+        });
+    // 角度的范围固定，角速度按数据自适应
+    let y_range = if variable % 2 == 0 {
+        (-PI, PI)
+    } else {
+        points
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &(_, y)| {
+                (lo.min(y), hi.max(y))
+            })
+    };
+    if !(x_range.0 < x_range.1 && y_range.0 < y_range.1) {
+        return;
+    }
+
+    let to_screen = egui::emath::RectTransform::from_to(
+        Rect::from_x_y_ranges(
+            x_range.0 as f32..=x_range.1 as f32,
+            y_range.1 as f32..=y_range.0 as f32,
+        ),
+        rect.shrink(4.0),
+    );
+    let color = ui.visuals().strong_text_color().gamma_multiply(0.6);
+    painter.extend(points.iter().map(|&(x, y)| {
+        Shape::rect_filled(
+            Rect::from_center_size(to_screen * Pos2::new(x as f32, y as f32), Vec2::splat(1.5)),
+            0.0,
+            color,
+        )
+    }));
+
+    let font = egui::FontId::monospace(10.0);
+    let text_color = ui.visuals().text_color();
+    for (pos, align, text) in [
+        (
+            rect.left_bottom(),
+            egui::Align2::LEFT_BOTTOM,
+            format!("{:.3}", x_range.0),
+        ),
+        (
+            rect.right_bottom(),
+            egui::Align2::RIGHT_BOTTOM,
+            format!("{:.3}", x_range.1),
+        ),
+        (
+            rect.left_top(),
+            egui::Align2::LEFT_TOP,
+            format!("{:.3}", y_range.1),
+        ),
+    ] {
+        painter.text(pos, align, text, font.clone(), text_color);
+    }
+    painter.text(
+        rect.center_top(),
+        egui::Align2::CENTER_TOP,
+        VARIABLE_NAMES[variable],
+        font,
+        Color32::GRAY,
+    );
+}
@@ -0,0 +1,513 @@
+use std::f64::consts::PI;
+
+use egui::{Color32, Pos2, Rect, Shape, Vec2};
+use nalgebra::{DMatrix, DVector};
+use num_complex::Complex64;
+use rand::Rng as _;
+
+use super::{
+    FractalPendulumApp, FractalPendulumAppSetting, Ode, State, equilibrium::settle,
+    pivot::PivotMode, tree, wrap_angle,
+};
+
+// LQR离散化用的时间步长
+const LQR_STEP: f64 = 0.01;
+// 倍增算法的最大迭代次数
+const LQR_ITERATIONS: usize = 60;
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy)]
+pub(super) enum ControlMode {
+    Off,
+    Pd,
+    Lqr,
+}
+
+// 状态反馈F = -K(y - y*)，目标为三根臂都竖直朝上
+#[derive(Clone)]
+pub(super) struct Controller {
+    target: State,
+    gain: DVector<f64>,
+    max_force: f64,
+}
+
+// 支点装在水平轨道上的小车上，小车的位置是最后一个广义坐标
+#[derive(Clone)]
+pub(super) struct Cart {
+    pub(super) mass: f64,
+    controller: Option<Controller>,
+}
+
+impl Cart {
+    pub(super) fn new(setting: &FractalPendulumAppSetting) -> Option<Self> {
+        setting.cart.then_some(Self {
+            mass: setting.cart_mass,
+            controller: None,
+        })
+    }
+}
+
+// 设计好的控制器，相关设置变了才重新设计。显含时间的驱动按重新设计时的时刻线性化
+#[derive(Default)]
+pub(super) struct ControllerCache {
+    key: Option<ControllerKey>,
+    controller: Option<Controller>,
+}
+
+// 影响控制器设计的全部设置，也就是方程里的全部参数。状态、时间、积分步长和画面相关的设置都不在其中
+#[derive(PartialEq)]
+pub(super) struct ControllerKey {
+    m: [f64; 3],
+    l: [f64; 3],
+    g: f64,
+    gravity_angle: f64,
+    frame_rotation: f64,
+    elastic: bool,
+    stiffness: [f64; 3],
+    spherical: bool,
+    compound: bool,
+    rod_mass: [f64; 3],
+    bob_size: [f64; 3],
+    cart: bool,
+    cart_mass: f64,
+    control_mode: ControlMode,
+    pd_gain: [f64; 4],
+    lqr_weight: [f64; 4],
+    max_force: f64,
+    modulated: bool,
+    modulation_amplitude: [f64; 7],
+    modulation_frequency: [f64; 7],
+    modulation_phase: [f64; 7],
+    magnetic: bool,
+    magnet_count: usize,
+    magnet_radius: f64,
+    magnet_strength: f64,
+    magnet_height: f64,
+    magnet_friction: f64,
+    pivot_mode: PivotMode,
+    pivot_response: f64,
+    pivot_amplitude: [f64; 2],
+    pivot_frequency: [f64; 2],
+    pivot_phase: [f64; 2],
+}
+
+impl ControllerKey {
+    pub(super) fn new(setting: &FractalPendulumAppSetting) -> Self {
+        Self {
+            m: setting.m,
+            l: setting.l,
+            g: setting.g,
+            gravity_angle: setting.gravity_angle,
+            frame_rotation: setting.frame_rotation,
+            elastic: setting.elastic,
+            stiffness: setting.stiffness,
+            spherical: setting.spherical,
+            compound: setting.compound,
+            rod_mass: setting.rod_mass,
+            bob_size: setting.bob_size,
+            cart: setting.cart,
+            cart_mass: setting.cart_mass,
+            control_mode: setting.control_mode,
+            pd_gain: setting.pd_gain,
+            lqr_weight: setting.lqr_weight,
+            max_force: setting.max_force,
+            modulated: setting.modulated,
+            modulation_amplitude: setting.modulation_amplitude,
+            modulation_frequency: setting.modulation_frequency,
+            modulation_phase: setting.modulation_phase,
+            magnetic: setting.magnetic,
+            magnet_count: setting.magnet_count,
+            magnet_radius: setting.magnet_radius,
+            magnet_strength: setting.magnet_strength,
+            magnet_height: setting.magnet_height,
+            magnet_friction: setting.magnet_friction,
+            pivot_mode: setting.pivot_mode,
+            pivot_response: setting.pivot_response,
+            pivot_amplitude: setting.pivot_amplitude,
+            pivot_frequency: setting.pivot_frequency,
+            pivot_phase: setting.pivot_phase,
+        }
+    }
+}
+
+impl Controller {
+    // 在不带控制的方程上求出倒立的平衡点并设计增益
+    pub(super) fn new(setting: &FractalPendulumAppSetting, ode: &Ode) -> Option<Self> {
+        let target = upright(ode);
+        let gain = match setting.control_mode {
+            ControlMode::Off => return None,
+            ControlMode::Pd => {
+                // θ1偏右时小车也要往右追，位置反馈的符号同样与直觉相反
+                let [kp, kd, kx, kv] = setting.pd_gain;
+                let mut gain = DVector::zeros(target.len());
+                gain[0] = -kp;
+                gain[1] = -kd;
+                gain[target.len() - 2] = -kx;
+                gain[target.len() - 1] = -kv;
+                gain
+            }
+            ControlMode::Lqr => lqr(ode, &target, setting.lqr_weight)?,
+        };
+        Some(Self {
+            target,
+            gain,
+            max_force: setting.max_force,
+        })
+    }
+}
+
+// 三根臂都朝上、小车在原点的平衡点，弹性杆的压缩量另外解出
+fn upright(ode: &Ode) -> State {
+    let mut y = State::zeros(2 * ode.dof());
+    y[0] = PI;
+    settle(ode, y)
+}
+
+// 在平衡点处线性化，离散化后用结构保持倍增算法解Riccati方程
+fn lqr(ode: &Ode, target: &State, weight: [f64; 4]) -> Option<DVector<f64>> {
+    let (a, b) = linearize(ode, target)?;
+    let (ad, bd) = discretize(&a, &b);
+    let force = weight[3];
+    let p = riccati(&ad, &bd, state_weight(target.len(), weight), force)?;
+
+    // K = (R + BᵀPB)⁻¹BᵀPA
+    let btp = bd.transpose() * &p;
+    let denominator = force + (&btp * &bd)[(0, 0)];
+    let gain = (btp * ad).transpose() / denominator;
+    Some(gain.column(0).into_owned())
+}
+
+// 平衡点处的线性化ẏ = Ay + Bu，u为给小车的推力
+fn linearize(ode: &Ode, target: &State) -> Option<(DMatrix<f64>, DVector<f64>)> {
+    let size = target.len();
+    let n = ode.dof();
+    let a = ode.jacobian(0.0, target);
+
+    // 推力只作用在小车坐标上，对各加速度的影响为M⁻¹的最后一列
+    let m_inv = ode.mass_matrix(target).cholesky()?.inverse();
+    let mut b = DVector::zeros(size);
+    for j in 0..n {
+        b[2 * j + 1] = m_inv[(j, n - 1)];
+    }
+    Some((a, b))
+}
+
+// 按步长LQR_STEP离散化，增广矩阵的指数同时给出离散化的A和B
+fn discretize(a: &DMatrix<f64>, b: &DVector<f64>) -> (DMatrix<f64>, DMatrix<f64>) {
+    let size = a.nrows();
+    let mut augmented = DMatrix::zeros(size + 1, size + 1);
+    augmented
+        .view_mut((0, 0), (size, size))
+        .copy_from(&(a * LQR_STEP));
+    augmented
+        .view_mut((0, size), (size, 1))
+        .copy_from(&(b * LQR_STEP));
+    let exponential = augmented.exp();
+    (
+        exponential.view((0, 0), (size, size)).into_owned(),
+        exponential.view((0, size), (size, 1)).into_owned(),
+    )
+}
+
+// 状态的权重矩阵，权重依次为角度、小车位置、各速度以及推力
+fn state_weight(size: usize, [angle, position, velocity, _]: [f64; 4]) -> DMatrix<f64> {
+    DMatrix::from_diagonal(&DVector::from_fn(size, |i, _| {
+        if i % 2 == 1 {
+            velocity
+        } else if i == size - 2 {
+            position
+        } else if i < 6 {
+            angle
+        } else {
+            // 弹性杆的伸长量不关心
+            0.0
+        }
+    }))
+}
+
+// 离散Riccati方程P = Q + AᵀPA - AᵀPB(R + BᵀPB)⁻¹BᵀPA的解，R为标量
+fn riccati(a: &DMatrix<f64>, b: &DMatrix<f64>, q: DMatrix<f64>, r: f64) -> Option<DMatrix<f64>> {
+    let size = a.nrows();
+    let identity = DMatrix::identity(size, size);
+    let mut ak = a.clone();
+    let mut gk = b * b.transpose() / r;
+    let mut hk = q;
+    for _ in 0..LQR_ITERATIONS {
+        let w = (&identity + &gk * &hk).try_inverse()?;
+        let a_next = &ak * &w * &ak;
+        let g_next = &gk + &ak * &w * &gk * ak.transpose();
+        let h_next = &hk + ak.transpose() * &hk * &w * &ak;
+        let change = (&h_next - &hk).norm();
+        (ak, gk, hk) = (a_next, g_next, h_next);
+        if change <= 1e-10 * hk.norm() {
+            break;
+        }
+    }
+    hk.iter().all(|p| p.is_finite()).then_some(hk)
+}
+
+impl Ode {
+    // 小车坐标在广义坐标中的位置
+    pub(super) fn cart_index(&self) -> Option<usize> {
+        self.cart.as_ref().map(|_| self.dof() - 1)
+    }
+
+    // 控制器给小车的推力
+    pub(super) fn control_force(&self, y: &State) -> f64 {
+        let Some(controller) = self.cart.as_ref().and_then(|cart| cart.controller.as_ref()) else {
+            return 0.0;
+        };
+        let mut error = y - &controller.target;
+        for i in [0, 2, 4] {
+            error[i] = wrap_angle(error[i]);
+        }
+        (-controller.gain.dot(&error)).clamp(-controller.max_force, controller.max_force)
+    }
+
+    // 装上设计好的控制器
+    pub(super) fn attach_controller(&mut self, controller: Option<&Controller>) {
+        if let Some(cart) = &mut self.cart {
+            cart.controller = controller.cloned();
+        }
+    }
+
+    // 小车上是否装了控制器，控制力不是保守力
+    pub(super) fn is_controlled(&self) -> bool {
+        self.cart
+            .as_ref()
+            .is_some_and(|cart| cart.controller.is_some())
+    }
+}
+
+impl FractalPendulumApp {
+    // 每帧开始时调用，影响控制器的设置变了才重新设计
+    pub(super) fn update_controller(&mut self) {
+        let key = ControllerKey::new(&self.setting);
+        let cache = &mut self.data.controller;
+        if cache.key.as_ref() != Some(&key) {
+            cache.controller = self
+                .setting
+                .cart
+                .then(|| Controller::new(&self.setting, &Ode::new(&self.setting)))
+                .flatten();
+            cache.key = Some(key);
+        }
+    }
+
+    pub(super) fn controller(&self) -> Option<&Controller> {
+        self.data.controller.controller.as_ref()
+    }
+
+    // 按给定的设置建方程，装上当前设置下设计好的控制器
+    pub(super) fn controlled_ode(&self, setting: &FractalPendulumAppSetting) -> Ode {
+        let mut ode = Ode::new(setting);
+        ode.attach_controller(self.controller());
+        ode
+    }
+
+    // 画轨道和小车，放在分形下面
+    pub(super) fn paint_cart(
+        &self,
+        painter: &egui::Painter,
+        to_screen: &egui::emath::RectTransform,
+    ) {
+        if !self.setting.cart {
+            return;
+        }
+        // 小车跟着支点一起动
+        let [px, py] = self.pivot_position();
+        let y = self.setting.y_offset + py;
+        let x = self.setting.x_offset + px + self.setting.cart_state[0];
+        let view = tree::View::new(to_screen);
+        let rect = painter.clip_rect();
+        let track = view.apply(Complex64::new(0.0, y));
+        painter.line_segment(
+            [
+                Pos2::new(rect.left(), track.y),
+                Pos2::new(rect.right(), track.y),
+            ],
+            (1.0, Color32::GRAY),
+        );
+        let scale = to_screen.scale().x;
+        painter.add(Shape::rect_filled(
+            Rect::from_center_size(
+                view.apply(Complex64::new(x, y)),
+                Vec2::new(0.6, 0.3) * scale,
+            ),
+            2.0,
+            Color32::DARK_GRAY,
+        ));
+    }
+
+    pub(super) fn cart_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.setting.cart, "启用")
+            .on_hover_text("支点装在水平轨道的小车上");
+        if !self.setting.cart {
+            return;
+        }
+
+        egui::Grid::new("小车网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("质量");
+                ui.add(
+                    egui::Slider::new(&mut self.setting.cart_mass, 0.1..=10.0)
+                        .logarithmic(true)
+                        .clamping(egui::SliderClamping::Never),
+                );
+                ui.end_row();
+
+                ui.label("位置");
+                ui.add(
+                    egui::Slider::new(&mut self.setting.cart_state[0], -10.0..=10.0)
+                        .clamping(egui::SliderClamping::Never),
+                );
+                ui.end_row();
+
+                ui.label("速度");
+                ui.add(
+                    egui::Slider::new(&mut self.setting.cart_state[1], -10.0..=10.0)
+                        .clamping(egui::SliderClamping::Never),
+                );
+                ui.end_row();
+            });
+
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.setting.control_mode, ControlMode::Off, "无控制");
+            ui.selectable_value(&mut self.setting.control_mode, ControlMode::Pd, "PD")
+                .on_hover_text("只反馈θ1和小车，通常只能竖住第一根臂");
+            ui.selectable_value(&mut self.setting.control_mode, ControlMode::Lqr, "LQR")
+                .on_hover_text(
+                    "在倒立平衡点处线性化后求最优增益，第二、三根臂挂在同一个球上，反对称的摆动很难从小车控制，只能在很小的偏离内竖住",
+                );
+        });
+
+        self.control_ui(ui);
+
+        if ui
+            .button("竖起来")
+            .on_hover_text("三根臂竖直朝上，加上极小的扰动，小车回到原点")
+            .clicked()
+        {
+            let mut rng = rand::rng();
+            self.setting.q = [PI, 0.0, 0.0, 0.0, 0.0, 0.0];
+            for qi in &mut self.setting.q {
+                *qi += rng.random_range(-1e-4..=1e-4);
+            }
+            self.setting.cart_state = [0.0; 2];
+        }
+    }
+
+    // 控制方式相关的参数和当前推力
+    fn control_ui(&mut self, ui: &mut egui::Ui) {
+        match self.setting.control_mode {
+            ControlMode::Off => {}
+            ControlMode::Pd => {
+                egui::Grid::new("PD网格")
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (gain, name) in self.setting.pd_gain.iter_mut().zip([
+                            "θ1比例",
+                            "θ1微分",
+                            "位置比例",
+                            "位置微分",
+                        ]) {
+                            ui.label(name);
+                            ui.add(egui::DragValue::new(gain).speed(0.1).range(0.0..=10000.0));
+                            ui.end_row();
+                        }
+                    });
+            }
+            ControlMode::Lqr => {
+                egui::Grid::new("LQR网格")
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (weight, name) in self.setting.lqr_weight.iter_mut().zip([
+                            "角度权重",
+                            "位置权重",
+                            "速度权重",
+                            "推力权重",
+                        ]) {
+                            ui.label(name);
+                            ui.add(egui::DragValue::new(weight).speed(0.01).range(1e-4..=1e4));
+                            ui.end_row();
+                        }
+                    });
+            }
+        }
+
+        if self.setting.control_mode != ControlMode::Off {
+            ui.add(
+                egui::DragValue::new(&mut self.setting.max_force)
+                    .speed(1.0)
+                    .range(0.0..=1e5)
+                    .prefix("最大推力："),
+            );
+
+            if self.controller().is_some() {
+                ui.label(format!(
+                    "当前推力：{:.3}",
+                    self.ode().control_force(&self.setting.state())
+                ));
+            } else {
+                ui.label("增益计算失败");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::complex_eigenvalues;
+
+    fn cart_ode() -> (Ode, State) {
+        let setting = FractalPendulumAppSetting {
+            cart: true,
+            control_mode: ControlMode::Lqr,
+            ..Default::default()
+        };
+        let ode = Ode::new(&setting);
+        let target = upright(&ode);
+        (ode, target)
+    }
+
+    #[test]
+    fn lqr_stabilizes_the_upright_equilibrium() {
+        let (ode, target) = cart_ode();
+        let weight = FractalPendulumAppSetting::default().lqr_weight;
+        let gain = lqr(&ode, &target, weight).expect("倒立平衡点应当能设计出增益");
+        let (a, b) = linearize(&ode, &target).expect("质量矩阵不应奇异");
+
+        // 不加控制时倒立不稳定，闭环A - BK的特征值实部全为负
+        let open = complex_eigenvalues(&a).expect("特征值应当能算出");
+        assert!(open.iter().any(|e| e.re > 0.0));
+        let closed = complex_eigenvalues(&(a - &b * gain.transpose())).expect("特征值应当能算出");
+        for e in &closed {
+            assert!(e.re < 0.0, "闭环特征值{e}的实部不为负");
+        }
+    }
+
+    #[test]
+    fn riccati_residual_is_small() {
+        let (ode, target) = cart_ode();
+        let weight = FractalPendulumAppSetting::default().lqr_weight;
+        let (a, b) = linearize(&ode, &target).expect("质量矩阵不应奇异");
+        let (ad, bd) = discretize(&a, &b);
+        let q = state_weight(target.len(), weight);
+        let r = weight[3];
+        let p = riccati(&ad, &bd, q.clone(), r).expect("Riccati方程应当有解");
+
+        let atpb = ad.transpose() * &p * &bd;
+        let denominator = r + (bd.transpose() * &p * &bd)[(0, 0)];
+        let residual = q + ad.transpose() * &p * &ad - &atpb * atpb.transpose() / denominator - &p;
+        assert!(
+            residual.norm() <= 1e-8 * p.norm(),
+            "残差{}，P的范数{}",
+            residual.norm(),
+            p.norm()
+        );
+    }
+}
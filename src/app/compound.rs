@@ -0,0 +1,47 @@
+use super::{FractalPendulumApp, FractalPendulumAppSetting};
+
+// 复摆：杆有自己的质量，小球有大小，两者都带来转动惯量
+#[derive(Clone, Copy)]
+pub(super) struct Compound {
+    // 各杆的质量，杆看成均匀细杆
+    pub(super) rod_mass: [f64; 3],
+    // 各小球的大小（圆盘半径），小球看成均匀实心圆盘，与碰撞半径无关
+    pub(super) bob_size: [f64; 3],
+}
+
+impl Compound {
+    pub(super) fn new(setting: &FractalPendulumAppSetting) -> Option<Self> {
+        setting.compound.then_some(Self {
+            rod_mass: setting.rod_mass,
+            bob_size: setting.bob_size,
+        })
+    }
+}
+
+impl FractalPendulumApp {
+    pub(super) fn compound_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.setting.compound, "启用")
+            .on_hover_text("杆按均匀细杆、小球按实心圆盘计入质量和转动惯量");
+        if !self.setting.compound {
+            return;
+        }
+
+        egui::Grid::new("复摆网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                for (i, mass) in self.setting.rod_mass.iter_mut().enumerate() {
+                    ui.label(format!("杆{}质量", i + 1));
+                    ui.add(egui::DragValue::new(mass).speed(0.01).range(0.0..=10.0));
+                    ui.end_row();
+                }
+
+                for (i, radius) in self.setting.bob_size.iter_mut().enumerate() {
+                    ui.label(format!("球{}半径", i + 1))
+                        .on_hover_text("为零时当作质点，大于零时按此半径绘制");
+                    ui.add(egui::DragValue::new(radius).speed(0.01).range(0.0..=1.0));
+                    ui.end_row();
+                }
+            });
+    }
+}
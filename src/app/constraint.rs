@@ -0,0 +1,342 @@
+use std::f64::consts::PI;
+
+use nalgebra::DVector;
+
+use super::{FractalPendulumApp, FractalPendulumAppSetting, Ode, State, dual::Dual, wrap_angle};
+
+// 一帧之内最多处理的碰撞次数，超过后只做投影
+const MAX_EVENTS: usize = 64;
+// 二分查找碰撞时刻的次数
+const BISECTIONS: usize = 40;
+// 接近速度低于此值时不再反弹
+const REST_SPEED: f64 = 0.05;
+
+#[derive(Clone, Copy, Default)]
+pub(super) struct Constraints {
+    // 各关节角度的上下限
+    joint_limit: Option<[[f64; 2]; 3]>,
+    // 第二、三个小球之间碰撞时的半径
+    bob_radius: Option<f64>,
+    restitution: f64,
+}
+
+impl Constraints {
+    pub(super) fn new(setting: &FractalPendulumAppSetting) -> Self {
+        Self {
+            joint_limit: setting.joint_limits.then_some(setting.joint_limit),
+            bob_radius: setting.bob_collision.then_some(setting.bob_radius),
+            restitution: setting.restitution,
+        }
+    }
+
+    pub(super) fn is_active(&self) -> bool {
+        self.joint_limit.is_some() || self.bob_radius.is_some()
+    }
+
+    fn contacts(&self) -> impl Iterator<Item = Contact> {
+        let joints = self
+            .joint_limit
+            .map(|_| (0..3).flat_map(|i| [Contact::Lower(i), Contact::Upper(i)]))
+            .into_iter()
+            .flatten();
+        joints.chain(self.bob_radius.map(|_| Contact::Bobs))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Contact {
+    Lower(usize),
+    Upper(usize),
+    Bobs,
+}
+
+impl Ode {
+    // 第二、三个小球之间的距离，对偶部分为沿第j个坐标的导数
+    fn bob_distance(&self, y: &State, j: Option<usize>) -> Dual {
+        let x: Vec<Dual> = (0..self.dof())
+            .map(|i| Dual::new(y[2 * i], if Some(i) == j { 1.0 } else { 0.0 }))
+            .collect();
+        let [_, p2, p3] = self.positions(&x, Dual::default());
+        (p2 - p3).norm_squared().sqrt()
+    }
+
+    // 约束的间隙，负数表示已经越过
+    fn gap(&self, contact: Contact, y: &State) -> f64 {
+        let limit = self.constraints.joint_limit.unwrap_or_default();
+        match contact {
+            Contact::Lower(i) => wrap_angle(y[2 * i]) - limit[i][0],
+            Contact::Upper(i) => limit[i][1] - wrap_angle(y[2 * i]),
+            Contact::Bobs => {
+                self.bob_distance(y, None).v - 2.0 * self.constraints.bob_radius.unwrap_or_default()
+            }
+        }
+    }
+
+    // 间隙对各广义坐标的梯度
+    fn gap_gradient(&self, contact: Contact, y: &State) -> DVector<f64> {
+        let n = self.dof();
+        match contact {
+            Contact::Lower(i) => DVector::from_fn(n, |j, _| if i == j { 1.0 } else { 0.0 }),
+            Contact::Upper(i) => DVector::from_fn(n, |j, _| if i == j { -1.0 } else { 0.0 }),
+            Contact::Bobs => DVector::from_fn(n, |j, _| self.bob_distance(y, Some(j)).d),
+        }
+    }
+
+    // 间隙的梯度以及按质量矩阵加权后的法向
+    fn normal(&self, contact: Contact, y: &State) -> Option<(DVector<f64>, DVector<f64>)> {
+        let j = self.gap_gradient(contact, y);
+        let m_inv_j = self.mass_matrix(y).cholesky()?.solve(&j);
+        Some((j, m_inv_j))
+    }
+
+    // 沿约束法向施加冲量，使间隙的变化率反向并乘以恢复系数
+    fn impulse(&self, contact: Contact, y: &State, restitution: f64) -> State {
+        let Some((j, m_inv_j)) = self.normal(contact, y) else {
+            return y.clone();
+        };
+        let v = self.velocity(y);
+        let approach = j.dot(&v);
+        if approach >= 0.0 {
+            return y.clone();
+        }
+
+        let v = v - &m_inv_j * ((1.0 + restitution) * approach / j.dot(&m_inv_j));
+        let mut y = y.clone();
+        for (i, vi) in v.iter().enumerate() {
+            y[2 * i + 1] = *vi;
+        }
+        y
+    }
+
+    // 把越过约束的部分沿法向推回去，并去掉继续往里的速度，用来处理贴着不动的情况
+    fn project(&self, y: &State) -> State {
+        let mut y = y.clone();
+        for contact in self.constraints.contacts() {
+            let gap = self.gap(contact, &y);
+            if gap < 0.0 {
+                if let Some((j, m_inv_j)) = self.normal(contact, &y) {
+                    let dx = &m_inv_j * (-gap / j.dot(&m_inv_j));
+                    for (i, dxi) in dx.iter().enumerate() {
+                        y[2 * i] += dxi;
+                    }
+                }
+                y = self.impulse(contact, &y, 0.0);
+            }
+        }
+        y
+    }
+
+    // 各广义速度
+    fn velocity(&self, y: &State) -> DVector<f64> {
+        DVector::from_fn(self.dof(), |i, _| y[2 * i + 1])
+    }
+
+    // 二分查找从时刻t0开始的这一段中间隙变为负数的时刻，返回相对t0的时间
+    fn crossing_time(&self, contact: Contact, y: &State, t0: f64, t: f64, h: f64) -> Option<f64> {
+        let (mut lo, mut hi) = (0.0, t);
+        for _ in 0..BISECTIONS {
+            let mid = 0.5 * (lo + hi);
+            let end = self.free_trajectory(y.clone(), t0, mid, h)?.pop()?;
+            if self.gap(contact, &end) < 0.0 {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        Some(lo)
+    }
+
+    // 分成长度不超过h的小段积分，每段结束时检查约束，越过时找到碰撞时刻并施加冲量
+    pub(super) fn constrained_trajectory(
+        &self,
+        y: &State,
+        t0: f64,
+        t: f64,
+        h: f64,
+    ) -> Option<Vec<State>> {
+        let mut y = self.project(y);
+        let mut trajectory = vec![y.clone()];
+        let mut remaining = t;
+        let mut events = 0;
+        // 当前时刻，每段从这里接着积，显含时间的驱动才不会每段从头开始
+        let mut elapsed = t0;
+
+        while remaining > 0.0 {
+            let dt = remaining.min(h);
+            let chunk = self.free_trajectory(y.clone(), elapsed, dt, h)?;
+            let end = chunk.last()?.clone();
+
+            // 这一段里最早发生的碰撞
+            let mut hit: Option<(f64, Contact)> = None;
+            if events < MAX_EVENTS {
+                for contact in self.constraints.contacts() {
+                    // 撞得很慢时当作贴着不动，留给后面的投影处理
+                    let approach = self.gap_gradient(contact, &end).dot(&self.velocity(&end));
+                    if self.gap(contact, &y) >= 0.0
+                        && self.gap(contact, &end) < 0.0
+                        && approach < -REST_SPEED
+                    {
+                        let tau = self.crossing_time(contact, &y, elapsed, dt, h)?;
+                        if hit.is_none_or(|(first, _)| tau < first) {
+                            hit = Some((tau, contact));
+                        }
+                    }
+                }
+            }
+
+            if let Some((tau, contact)) = hit {
+                if tau > 0.0 {
+                    trajectory.extend(
+                        self.free_trajectory(y, elapsed, tau, h)?
+                            .into_iter()
+                            .skip(1),
+                    );
+                    y = trajectory.last()?.clone();
+                }
+                y = self.impulse(contact, &y, self.constraints.restitution);
+                trajectory.push(y.clone());
+                remaining -= tau;
+                elapsed += tau;
+                events += 1;
+            } else {
+                trajectory.extend(chunk.into_iter().skip(1));
+                y = self.project(&end);
+                *trajectory.last_mut()? = y.clone();
+                remaining -= dt;
+                elapsed += dt;
+            }
+        }
+
+        Some(trajectory)
+    }
+}
+
+impl FractalPendulumApp {
+    pub(super) fn constraint_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.setting.joint_limits, "关节限位");
+        if self.setting.joint_limits {
+            egui::Grid::new("关节限位网格")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    for (i, [lower, upper]) in self.setting.joint_limit.iter_mut().enumerate() {
+                        ui.label(format!("θ{}", i + 1));
+                        ui.add(
+                            egui::DragValue::new(lower)
+                                .speed(0.01)
+                                .range(-PI..=*upper)
+                                .prefix("下限："),
+                        );
+                        ui.add(
+                            egui::DragValue::new(upper)
+                                .speed(0.01)
+                                .range(*lower..=PI)
+                                .prefix("上限："),
+                        );
+                        ui.end_row();
+                    }
+                });
+        }
+
+        ui.checkbox(&mut self.setting.bob_collision, "小球碰撞")
+            .on_hover_text("第二、三个小球之间的碰撞，开启后小球按碰撞半径绘制");
+        if self.setting.bob_collision {
+            ui.add(
+                egui::DragValue::new(&mut self.setting.bob_radius)
+                    .speed(0.01)
+                    .range(0.01..=10.0)
+                    .prefix("碰撞半径："),
+            );
+        }
+
+        if self.setting.joint_limits || self.setting.bob_collision {
+            ui.add(egui::Slider::new(&mut self.setting.restitution, 0.0..=1.0).text("恢复系数"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_energy(ode: &Ode, y: &State) -> f64 {
+        let (t, v) = ode.energy(y);
+        t + v
+    }
+
+    #[test]
+    fn joint_limit_stops_the_arm() {
+        // 第一根臂以很快的角速度甩向上限
+        let setting = FractalPendulumAppSetting {
+            q: [0.4, 5.0, 0.0, 0.0, 0.0, 0.0],
+            joint_limits: true,
+            joint_limit: [[-0.5, 0.5]; 3],
+            restitution: 0.5,
+            ..Default::default()
+        };
+        let ode = Ode::new(&setting);
+        let trajectory = ode
+            .constrained_trajectory(&setting.state(), 0.0, 0.5, 0.01)
+            .expect("积分不应出错");
+
+        for y in &trajectory {
+            for i in 0..3 {
+                assert!(
+                    (-0.5 - 1e-9..=0.5 + 1e-9).contains(&y[2 * i]),
+                    "θ{}越过了限位：{}",
+                    i + 1,
+                    y[2 * i]
+                );
+            }
+        }
+        // 撞上以后反弹回来
+        assert!(trajectory.iter().any(|y| y[1] < 0.0), "第一根臂没有反弹");
+    }
+
+    #[test]
+    fn elastic_collision_keeps_kinetic_energy() {
+        // 第二、三个小球相向运动
+        let setting = FractalPendulumAppSetting {
+            q: [0.0, 0.0, 0.3, -2.0, -0.3, 2.0],
+            bob_collision: true,
+            restitution: 1.0,
+            ..Default::default()
+        };
+        let ode = Ode::new(&setting);
+        let y = setting.state();
+        let approach = |y: &State| ode.gap_gradient(Contact::Bobs, y).dot(&ode.velocity(y));
+        assert!(approach(&y) < 0.0);
+
+        let after = ode.impulse(Contact::Bobs, &y, 1.0);
+        let (before, kinetic) = (ode.energy(&y).0, ode.energy(&after).0);
+        assert!(
+            (kinetic - before).abs() <= 1e-12 * before,
+            "动能从{before}变成了{kinetic}"
+        );
+        assert!(
+            (approach(&after) + approach(&y)).abs() <= 1e-12 * approach(&y).abs(),
+            "接近速度没有原样反向"
+        );
+    }
+
+    #[test]
+    fn elastic_collisions_keep_total_energy() {
+        let setting = FractalPendulumAppSetting {
+            q: [0.0, 0.0, 0.3, -2.0, -0.3, 2.0],
+            bob_collision: true,
+            restitution: 1.0,
+            ..Default::default()
+        };
+        let ode = Ode::new(&setting);
+        let y = setting.state();
+        let end = ode
+            .constrained_trajectory(&y, 0.0, 1.0, 0.01)
+            .and_then(|mut trajectory| trajectory.pop())
+            .expect("积分不应出错");
+        let (before, after) = (total_energy(&ode, &y), total_energy(&ode, &end));
+        assert!(
+            (after - before).abs() <= 1e-6 * before.abs(),
+            "能量从{before}变成了{after}"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+
+use num_complex::Complex64;
+
+use super::FractalPendulumApp;
+
+// 计盒维数最多用到第几层的末梢
+const MAX_LEAF_DEPTH: usize = 14;
+// 最细分到包围盒边长的2^-MAX_SCALES
+const MAX_SCALES: i32 = 16;
+
+// 相似维数：r1^D + r2^D = 1的解。左边随D单调减，二分即可；比例不小于1时极限集无界，没有意义
+fn similarity_dimension(r1: f64, r2: f64) -> Option<f64> {
+    if !(0.0..1.0).contains(&r1) || !(0.0..1.0).contains(&r2) || r1 + r2 == 0.0 {
+        return None;
+    }
+    let f = |d: f64| r1.powf(d) + r2.powf(d) - 1.0;
+    let (mut lo, mut hi) = (0.0, 1.0);
+    while f(hi) > 0.0 {
+        hi *= 2.0;
+    }
+    for _ in 0..100 {
+        let mid = 0.5 * (lo + hi);
+        if f(mid) > 0.0 {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    Some(0.5 * (lo + hi))
+}
+
+// 计盒维数：把末梢所在的包围盒逐次对半细分，数被占的格子，对log N和log(1/ε)做最小二乘。
+// 格子数接近点数时已经分辨不出更细的结构，这些尺度不参与拟合
+fn box_counting_dimension(points: &[Complex64]) -> Option<f64> {
+    let (min, max) = points.iter().fold(
+        (
+            Complex64::new(f64::INFINITY, f64::INFINITY),
+            Complex64::new(f64::NEG_INFINITY, f64::NEG_INFINITY),
+        ),
+        |(min, max), p| {
+            (
+                Complex64::new(min.re.min(p.re), min.im.min(p.im)),
+                Complex64::new(max.re.max(p.re), max.im.max(p.im)),
+            )
+        },
+    );
+    let size = (max.re - min.re).max(max.im - min.im);
+    if !size.is_finite() || size <= 0.0 {
+        return None;
+    }
+
+    let mut samples = Vec::new();
+    let mut boxes = HashSet::with_capacity(points.len());
+    for k in 1..=MAX_SCALES {
+        let cells = f64::from(1 << k);
+        boxes.clear();
+        // 落在包围盒右上边界上的点归到最后一格，不然边界会多出一整排格子
+        let last = (1_i64 << k) - 1;
+        boxes.extend(points.iter().map(|p| {
+            (
+                (((p.re - min.re) / size * cells) as i64).min(last),
+                (((p.im - min.im) / size * cells) as i64).min(last),
+            )
+        }));
+        if boxes.len() * 8 > points.len() {
+            break;
+        }
+        samples.push((cells.ln(), (boxes.len() as f64).ln()));
+    }
+    if samples.len() < 3 {
+        return None;
+    }
+
+    let n = samples.len() as f64;
+    let (sx, sy) = samples
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+    let (mx, my) = (sx / n, sy / n);
+    let (sxy, sxx) = samples.iter().fold((0.0, 0.0), |(sxy, sxx), (x, y)| {
+        (sxy + (x - mx) * (y - my), sxx + (x - mx) * (x - mx))
+    });
+    Some(sxy / sxx)
+}
+
+impl FractalPendulumApp {
+    // 当前状态下最后一层线段的末梢，在世界坐标里算，不受画面剪裁影响
+    fn leaves(&self) -> Vec<Complex64> {
+        // 只用到世界坐标，画面随便给一个
+        let to_screen = self.to_screen(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::Vec2::splat(1.0),
+        ));
+        let depth = self.render_depth().min(MAX_LEAF_DEPTH);
+        let (grower, root) = self.grower(&self.setting.state(), [0.0; 2], depth, &to_screen);
+        let mut nodes = vec![root];
+        for _ in 0..depth {
+            nodes = nodes
+                .iter()
+                .flat_map(|node| {
+                    let [t1, t2] = grower.transforms;
+                    [node.apply(t1, 0), node.apply(t2, 1)]
+                })
+                .collect();
+        }
+        nodes.iter().map(|node| node.start + node.vec).collect()
+    }
+
+    pub(super) fn dimension_ui(&self, ui: &mut egui::Ui) {
+        if self.setting.spherical {
+            ui.label("球面摆的分形不在一个平面里，这里不计算");
+            return;
+        }
+
+        let [l1, l2, l3] = self.setting.lengths(&self.setting.state());
+        let similarity = similarity_dimension(l2 / l1, l3 / l1);
+        let box_counting = box_counting_dimension(&self.leaves());
+        let format = |d: Option<f64>| d.map_or_else(|| "—".to_owned(), |d| format!("{d:.4}"));
+
+        egui::Grid::new("分形维数网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("相似维数").on_hover_text(
+                    "r1^D + r2^D = 1的解，r1 = l2/l1，r2 = l3/l1。只由比例决定；两个分支重叠时只是实际维数的上界",
+                );
+                ui.label(format(similarity));
+                ui.end_row();
+
+                ui.label("计盒维数").on_hover_text(format!(
+                    "由最后一层（最多第{MAX_LEAF_DEPTH}层）线段末梢的位置逐次细分格子数出来，随角度变化，分支重叠时比相似维数小"
+                ));
+                ui.label(format(box_counting));
+                ui.end_row();
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similarity_dimension_of_known_ratios() {
+        let cases = [
+            (0.5, 0.5, 1.0),
+            (
+                std::f64::consts::FRAC_1_SQRT_2,
+                std::f64::consts::FRAC_1_SQRT_2,
+                2.0,
+            ),
+            (1.0 / 3.0, 1.0 / 3.0, 2.0_f64.ln() / 3.0_f64.ln()),
+        ];
+        for (r1, r2, expected) in cases {
+            let d = similarity_dimension(r1, r2).expect("比例小于1时应当有解");
+            assert!((d - expected).abs() < 1e-9, "r = {r1}, {r2}时得到{d}");
+        }
+        assert!(similarity_dimension(1.0, 0.5).is_none());
+        assert!(similarity_dimension(0.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn box_counting_dimension_of_known_sets() {
+        // 线段
+        let line: Vec<Complex64> = (0..100_000)
+            .map(|i| Complex64::new(f64::from(i) / 100_000.0, 0.3 * f64::from(i) / 100_000.0))
+            .collect();
+        // 实心正方形
+        let square: Vec<Complex64> = (0..300 * 300)
+            .map(|i| Complex64::new(f64::from(i % 300), f64::from(i / 300)))
+            .collect();
+        // 谢尔平斯基三角形的第10层，维数log2(3)
+        let mut sierpinski = vec![Complex64::new(0.0, 0.0)];
+        for level in 1..=10 {
+            let step = 0.5_f64.powi(level);
+            sierpinski = sierpinski
+                .iter()
+                .flat_map(|&p| {
+                    [
+                        p,
+                        p + Complex64::new(step, 0.0),
+                        p + Complex64::new(0.0, step),
+                    ]
+                })
+                .collect();
+        }
+
+        for (points, expected) in [(line, 1.0), (square, 2.0), (sierpinski, 3.0_f64.log2())] {
+            let d = box_counting_dimension(&points).expect("点足够多时应当能算出维数");
+            assert!((d - expected).abs() < 0.05, "应为{expected}，得到{d}");
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+// 前向自动微分用的对偶数 v + dε，ε² = 0，只实现用得到的运算
+#[derive(Clone, Copy, Default)]
+pub(super) struct Dual {
+    pub(super) v: f64,
+    pub(super) d: f64,
+}
+
+impl Dual {
+    pub(super) fn new(v: f64, d: f64) -> Self {
+        Self { v, d }
+    }
+
+    pub(super) fn constant(v: f64) -> Self {
+        Self { v, d: 0.0 }
+    }
+
+    pub(super) fn sin(self) -> Self {
+        Self::new(self.v.sin(), self.d * self.v.cos())
+    }
+
+    pub(super) fn cos(self) -> Self {
+        Self::new(self.v.cos(), -self.d * self.v.sin())
+    }
+
+    pub(super) fn sqrt(self) -> Self {
+        let s = self.v.sqrt();
+        Self::new(s, self.d / (2.0 * s))
+    }
+}
+
+impl From<f64> for Dual {
+    fn from(v: f64) -> Self {
+        Self::constant(v)
+    }
+}
+
+impl Add for Dual {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.v + rhs.v, self.d + rhs.d)
+    }
+}
+
+impl Sub for Dual {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.v - rhs.v, self.d - rhs.d)
+    }
+}
+
+impl Mul for Dual {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(self.v * rhs.v, self.d * rhs.v + self.v * rhs.d)
+    }
+}
+
+impl Div for Dual {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        Self::new(
+            self.v / rhs.v,
+            (self.d * rhs.v - self.v * rhs.d) / (rhs.v * rhs.v),
+        )
+    }
+}
+
+impl Neg for Dual {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self::new(-self.v, -self.d)
+    }
+}
+
+impl Add<f64> for Dual {
+    type Output = Self;
+    fn add(self, rhs: f64) -> Self {
+        Self::new(self.v + rhs, self.d)
+    }
+}
+
+impl Sub<f64> for Dual {
+    type Output = Self;
+    fn sub(self, rhs: f64) -> Self {
+        Self::new(self.v - rhs, self.d)
+    }
+}
+
+impl Mul<f64> for Dual {
+    type Output = Self;
+    fn mul(self, rhs: f64) -> Self {
+        Self::new(self.v * rhs, self.d * rhs)
+    }
+}
+
+// 平面上的对偶数向量
+#[derive(Clone, Copy, Default)]
+pub(super) struct DualVec2 {
+    pub(super) x: Dual,
+    pub(super) y: Dual,
+}
+
+impl DualVec2 {
+    pub(super) fn new(x: Dual, y: Dual) -> Self {
+        Self { x, y }
+    }
+
+    // 画图坐标系下沿臂方向的单位向量，角度为零时竖直向下
+    pub(super) fn direction(angle: Dual) -> Self {
+        Self::new(-angle.sin(), angle.cos())
+    }
+
+    // 逆时针转过直角
+    pub(super) fn perp(self) -> Self {
+        Self::new(-self.y, self.x)
+    }
+
+    pub(super) fn dot(self, rhs: Self) -> Dual {
+        self.x * rhs.x + self.y * rhs.y
+    }
+
+    pub(super) fn norm_squared(self) -> Dual {
+        self.dot(self)
+    }
+}
+
+impl Add for DualVec2 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl Sub for DualVec2 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl Mul<Dual> for DualVec2 {
+    type Output = Self;
+    fn mul(self, rhs: Dual) -> Self {
+        Self::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+impl Mul<f64> for DualVec2 {
+    type Output = Self;
+    fn mul(self, rhs: f64) -> Self {
+        Self::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+// 空间中的对偶数向量，坐标轴与画图时一致：x向右，y向下，z指向屏幕里面
+#[derive(Clone, Copy, Default)]
+pub(super) struct DualVec3 {
+    pub(super) x: Dual,
+    pub(super) y: Dual,
+    pub(super) z: Dual,
+}
+
+impl DualVec3 {
+    pub(super) fn new(x: Dual, y: Dual, z: Dual) -> Self {
+        Self { x, y, z }
+    }
+
+    // 竖直向下的单位向量
+    pub(super) fn down() -> Self {
+        Self::new(Dual::default(), Dual::constant(1.0), Dual::default())
+    }
+
+    // 绕z轴转过angle，角度为正时向下的向量转向左边，与平面上的角度一致
+    pub(super) fn rotate_z(self, angle: Dual) -> Self {
+        let (sin, cos) = (angle.sin(), angle.cos());
+        Self::new(
+            self.x * cos - self.y * sin,
+            self.x * sin + self.y * cos,
+            self.z,
+        )
+    }
+
+    // 绕z轴转动时对角度的导数
+    pub(super) fn rotate_z_rate(self, angle: Dual) -> Self {
+        let (sin, cos) = (angle.sin(), angle.cos());
+        Self::new(
+            -self.x * sin - self.y * cos,
+            self.x * cos - self.y * sin,
+            Dual::default(),
+        )
+    }
+
+    // 绕x轴转过angle，角度为正时向下的向量转向屏幕里面
+    pub(super) fn rotate_x(self, angle: Dual) -> Self {
+        let (sin, cos) = (angle.sin(), angle.cos());
+        Self::new(
+            self.x,
+            self.y * cos - self.z * sin,
+            self.y * sin + self.z * cos,
+        )
+    }
+
+    // 绕x轴转动时对角度的导数
+    pub(super) fn rotate_x_rate(self, angle: Dual) -> Self {
+        let (sin, cos) = (angle.sin(), angle.cos());
+        Self::new(
+            Dual::default(),
+            -self.y * sin - self.z * cos,
+            self.y * cos - self.z * sin,
+        )
+    }
+
+    pub(super) fn dot(self, rhs: Self) -> Dual {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub(super) fn norm_squared(self) -> Dual {
+        self.dot(self)
+    }
+}
+
+impl Add for DualVec3 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl Sub for DualVec3 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Mul<Dual> for DualVec3 {
+    type Output = Self;
+    fn mul(self, rhs: Dual) -> Self {
+        Self::new(self.x * rhs, self.y * rhs, self.z * rhs)
+    }
+}
@@ -0,0 +1,48 @@
+use super::FractalPendulumApp;
+
+impl FractalPendulumApp {
+    pub(super) fn elastic_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.setting.elastic, "启用")
+            .on_hover_text("三根杆换成弹簧，常量中的l作为静止长度，分形的比例随伸缩变化");
+        if !self.setting.elastic {
+            return;
+        }
+
+        egui::Grid::new("弹性杆网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                for (i, k) in self.setting.stiffness.iter_mut().enumerate() {
+                    ui.label(format!("k{}", i + 1)).on_hover_text("劲度系数");
+                    ui.add(
+                        egui::Slider::new(k, 1.0..=1000.0)
+                            .logarithmic(true)
+                            .clamping(egui::SliderClamping::Never),
+                    );
+                    ui.end_row();
+                }
+
+                for i in 0..3 {
+                    ui.label(format!("Δl{}", i + 1)).on_hover_text("伸长量");
+                    ui.add(
+                        egui::Slider::new(&mut self.setting.stretch[2 * i], -1.0..=1.0)
+                            .clamping(egui::SliderClamping::Never),
+                    );
+                    ui.end_row();
+                }
+
+                for i in 0..3 {
+                    ui.label(format!("Δl{}'", i + 1)).on_hover_text("伸长速度");
+                    ui.add(
+                        egui::Slider::new(&mut self.setting.stretch[2 * i + 1], -10.0..=10.0)
+                            .clamping(egui::SliderClamping::Never),
+                    );
+                    ui.end_row();
+                }
+            });
+
+        if ui.button("复位").clicked() {
+            self.setting.stretch = [0.0; 6];
+        }
+    }
+}
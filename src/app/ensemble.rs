@@ -0,0 +1,228 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use egui::{Mesh, Pos2, Rect, Shape, Vec2};
+use rand::Rng as _;
+
+use super::{FractalPendulumApp, Ode, State, tree, wrap_angle};
+
+// 发散曲线最多保留的点数
+const HISTORY: usize = 2000;
+
+pub(super) struct EnsembleData {
+    enabled: bool,
+    count: usize,
+    epsilon: f64,
+    skeleton: bool,
+    copies: Vec<State>,
+    // 每个副本一个网格，顶点缓冲留到下一帧接着用
+    meshes: Vec<Arc<Mesh>>,
+    time: f64,
+    // 各副本与主摆状态距离的平均值随时间的变化
+    divergence: VecDeque<(f64, f64)>,
+}
+
+impl Default for EnsembleData {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            count: 8,
+            epsilon: 1e-6,
+            skeleton: false,
+            copies: Vec::new(),
+            meshes: Vec::new(),
+            time: 0.0,
+            divergence: VecDeque::new(),
+        }
+    }
+}
+
+impl FractalPendulumApp {
+    // 以当前状态为中心重新撒副本
+    fn reset_ensemble(&mut self) {
+        let mut rng = rand::rng();
+        let data = &mut self.data.ensemble;
+        let epsilon = data.epsilon;
+        data.copies = (0..data.count)
+            .map(|_| {
+                let mut y = self.setting.state();
+                for yi in &mut y {
+                    *yi += rng.random_range(-epsilon..=epsilon);
+                }
+                y
+            })
+            .collect();
+        data.time = 0.0;
+        data.divergence.clear();
+    }
+
+    // 和主摆用同一组参数迭代，算不下去的副本直接丢掉
+    pub(super) fn step_ensemble(&mut self, ode: &Ode) {
+        if !self.data.ensemble.enabled || self.data.ensemble.copies.is_empty() {
+            return;
+        }
+        // 切换模型后状态的维数变了，副本需要重新撒
+        let main = self.setting.state();
+        if self.data.ensemble.copies[0].len() != main.len() {
+            self.reset_ensemble();
+        }
+
+        let data = &mut self.data.ensemble;
+
+        data.copies.retain_mut(|y| {
+            match ode.flow(y.clone(), 0.0, self.setting.delta_t, self.setting.h) {
+                Some(end) => {
+                    *y = end;
+                    for &i in ode.angle_indices() {
+                        y[i] = wrap_angle(y[i]);
+                    }
+                    true
+                }
+                None => false,
+            }
+        });
+        if data.copies.is_empty() {
+            return;
+        }
+
+        data.time += self.setting.delta_t;
+        let mean = data
+            .copies
+            .iter()
+            .map(|y| ode.state_difference(y, &main).norm())
+            .sum::<f64>()
+            / data.copies.len() as f64;
+        data.divergence.push_back((data.time, mean));
+        if data.divergence.len() > HISTORY {
+            data.divergence.pop_front();
+        }
+    }
+
+    // 副本各用一个固定色相，画在主摆下面
+    pub(super) fn paint_ensemble(
+        &mut self,
+        painter: &egui::Painter,
+        to_screen: &egui::emath::RectTransform,
+    ) {
+        if !self.data.ensemble.enabled {
+            return;
+        }
+
+        let depth = if self.data.ensemble.skeleton {
+            1
+        } else {
+            self.render_depth()
+        };
+        let dimension = self.setting.state().len();
+        let feather = 1.0 / painter.ctx().pixels_per_point();
+        let count = self.data.ensemble.copies.len();
+        self.data.ensemble.meshes.resize_with(count, Arc::default);
+        for k in 0..count {
+            let y = &self.data.ensemble.copies[k];
+            // 切换模型后还没重新撒的副本先不画
+            if y.len() != dimension {
+                continue;
+            }
+            let hue = std::f32::consts::TAU * k as f32 / count as f32;
+            let (segments, balls, _) = self.tree_shapes(y, hue, hue, depth, to_screen);
+            let mesh = &mut self.data.ensemble.meshes[k];
+            tree::tessellate(tree::reuse(mesh), &segments, feather);
+            painter.add(Shape::mesh(Arc::clone(mesh)));
+            painter.extend(balls.into_iter().rev());
+        }
+    }
+
+    pub(super) fn ensemble_ui(&mut self, ui: &mut egui::Ui) {
+        let data = &mut self.data.ensemble;
+        let mut reset = ui.checkbox(&mut data.enabled, "启用").changed() && data.enabled;
+
+        egui::Grid::new("系综网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("副本数");
+                ui.add(egui::DragValue::new(&mut data.count).range(1..=64));
+                ui.end_row();
+
+                ui.label("扰动幅度")
+                    .on_hover_text("每个副本的各个分量加上该幅度以内的随机扰动");
+                ui.add(
+                    egui::DragValue::new(&mut data.epsilon)
+                        .speed(1e-6)
+                        .range(0.0..=1.0),
+                );
+                ui.end_row();
+
+                ui.label("只画骨架");
+                ui.checkbox(&mut data.skeleton, "");
+                ui.end_row();
+            });
+
+        reset |= ui.button("重新生成").clicked();
+        if reset {
+            self.reset_ensemble();
+        }
+
+        plot_divergence(ui, &self.data.ensemble.divergence);
+    }
+}
+
+// 纵轴取对数，指数发散时是一条直线
+fn plot_divergence(ui: &mut egui::Ui, divergence: &VecDeque<(f64, f64)>) {
+    let points: Vec<(f64, f64)> = divergence
+        .iter()
+        .filter(|(_, d)| *d > 0.0)
+        .map(|&(t, d)| (t, d.log10()))
+        .collect();
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return;
+    };
+    let (lo, hi) = points
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &(_, y)| {
+            (lo.min(y), hi.max(y))
+        });
+    if !(first.0 < last.0 && lo < hi) {
+        return;
+    }
+
+    let (response, painter) =
+        ui.allocate_painter(Vec2::new(ui.available_width(), 100.0), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+
+    let to_screen = egui::emath::RectTransform::from_to(
+        Rect::from_x_y_ranges(first.0 as f32..=last.0 as f32, hi as f32..=lo as f32),
+        rect.shrink(4.0),
+    );
+    painter.add(Shape::line(
+        points
+            .iter()
+            .map(|&(t, y)| to_screen * Pos2::new(t as f32, y as f32))
+            .collect(),
+        ui.visuals().widgets.active.fg_stroke,
+    ));
+
+    let font = egui::FontId::monospace(10.0);
+    let color = ui.visuals().text_color();
+    painter.text(
+        rect.left_top(),
+        egui::Align2::LEFT_TOP,
+        format!("1e{hi:.1}"),
+        font.clone(),
+        color,
+    );
+    painter.text(
+        rect.left_bottom(),
+        egui::Align2::LEFT_BOTTOM,
+        format!("1e{lo:.1}"),
+        font.clone(),
+        color,
+    );
+    painter.text(
+        rect.right_bottom(),
+        egui::Align2::RIGHT_BOTTOM,
+        format!("t = {:.2}", last.0),
+        font,
+        color,
+    );
+}
@@ -0,0 +1,185 @@
+use std::{f64::consts::PI, time::Duration};
+
+use nalgebra::{DMatrix, DVector};
+use num_complex::Complex64;
+use ode_solvers::System as _;
+use rand::Rng as _;
+
+use super::{FractalPendulumApp, Ode, State, cart::ControllerKey, complex_eigenvalues, wrap_angle};
+
+// 平衡点：角速度全为零，三根臂各自竖直朝上或朝下
+struct Equilibrium {
+    // 三根臂的绝对方向是否朝上
+    up: [bool; 3],
+    y: State,
+    // 线性化后的特征值
+    eigenvalues: Vec<Complex64>,
+    // 实部为正的特征值个数，即不稳定方向数
+    unstable: usize,
+    // 实部为负的特征值个数，全部为负时是汇，渐近稳定
+    decaying: usize,
+}
+
+impl Equilibrium {
+    fn label(&self) -> String {
+        self.up
+            .iter()
+            .map(|&up| if up { '↑' } else { '↓' })
+            .collect()
+    }
+}
+
+// 平衡点依赖的全部输入，方程显含时间时还要加上线性化的时刻
+#[derive(PartialEq)]
+struct Key {
+    parameters: ControllerKey,
+    time: f64,
+}
+
+// 上次算出的平衡点，参数不变时每帧直接用
+#[derive(Default)]
+pub(super) struct EquilibriumCache {
+    key: Option<Key>,
+    equilibria: Vec<Equilibrium>,
+}
+
+// 枚举当前参数下的全部平衡点，并在每个点处线性化
+fn equilibria(ode: &Ode) -> Vec<Equilibrium> {
+    (0..8)
+        .map(|i| {
+            let up = [i & 1 != 0, i & 2 != 0, i & 4 != 0];
+            let [a1, a2, a3] = up.map(|up| if up { PI } else { 0.0 });
+            // θ2、θ3是相对第一根臂的角度，弹性杆的伸长量从零开始找
+            let mut y = State::zeros(2 * ode.dof());
+            y[0] = a1;
+            y[2] = wrap_angle(a2 - a1);
+            y[4] = wrap_angle(a3 - a1);
+            let y = settle(ode, y);
+
+            let eigenvalues = complex_eigenvalues(&ode.jacobian(0.0, &y)).unwrap_or_default();
+
+            // 保守系统的特征值成对出现，中心处的实部只剩数值误差
+            let scale = eigenvalues.iter().map(|e| e.norm()).fold(1.0, f64::max);
+            let unstable = eigenvalues.iter().filter(|e| e.re > 1e-6 * scale).count();
+            let decaying = eigenvalues.iter().filter(|e| e.re < -1e-6 * scale).count();
+
+            Equilibrium {
+                up,
+                y,
+                eigenvalues,
+                unstable,
+                decaying,
+            }
+        })
+        .collect()
+}
+
+// 角度已经是平衡位置，弹性杆的伸长量还要用牛顿法解出受力平衡的位置
+pub(super) fn settle(ode: &Ode, mut y: State) -> State {
+    let n = ode.dof();
+    for _ in 0..20 {
+        let mut dy = State::zeros(2 * n);
+        ode.system(0.0, &y, &mut dy);
+        let acceleration = DVector::from_fn(n, |i, _| dy[2 * i + 1]);
+        if acceleration.norm() < 1e-12 {
+            break;
+        }
+
+        // 加速度对坐标的导数
+        let jacobian = ode.jacobian(0.0, &y);
+        let block = DMatrix::from_fn(n, n, |i, j| jacobian[(2 * i + 1, 2 * j)]);
+        let Ok(step) = block.svd(true, true).solve(&acceleration, 1e-12) else {
+            break;
+        };
+        for (i, si) in step.iter().enumerate() {
+            y[2 * i] -= si;
+        }
+    }
+    y
+}
+
+impl FractalPendulumApp {
+    pub(super) fn equilibrium_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::DragValue::new(&mut self.data.equilibrium_perturbation)
+                .speed(0.001)
+                .range(0.0..=1.0)
+                .prefix("扰动幅度："),
+        )
+        .on_hover_text("跳转时给每个分量加上该幅度以内的随机扰动");
+
+        let ode = self.controlled_ode(&self.setting);
+        let key = Key {
+            parameters: ControllerKey::new(&self.setting),
+            time: if ode.is_autonomous() {
+                0.0
+            } else {
+                self.setting.time
+            },
+        };
+        let cache = &mut self.data.equilibrium;
+        if cache.key.as_ref() != Some(&key) {
+            cache.equilibria = equilibria(&ode);
+            cache.key = Some(key);
+        }
+        let mut target = None;
+
+        egui::Grid::new("平衡点网格")
+            .num_columns(3)
+            .striped(true)
+            .show(ui, |ui| {
+                for equilibrium in &cache.equilibria {
+                    ui.label(equilibrium.label())
+                        .on_hover_text("三根臂的绝对方向");
+
+                    let eigenvalues = equilibrium
+                        .eigenvalues
+                        .iter()
+                        .map(|e| format!("{:.4}{:+.4}i", e.re, e.im))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    if equilibrium.eigenvalues.is_empty() {
+                        ui.label("计算失败");
+                    } else if equilibrium.unstable == 0 {
+                        // 有阻力或控制时是汇，保守系统是中心
+                        let (label, kind) = if equilibrium.decaying == equilibrium.eigenvalues.len()
+                        {
+                            ("渐近稳定", "特征值实部全为负，线性化后为汇")
+                        } else if equilibrium.decaying == 0 {
+                            ("稳定", "特征值实部全为零，线性化后为中心")
+                        } else {
+                            ("稳定", "特征值实部有负有零，部分方向衰减，其余为中心")
+                        };
+                        ui.label(label)
+                            .on_hover_text(format!("{kind}，特征值：\n{eigenvalues}"));
+                    } else {
+                        ui.label(format!("不稳定（{}）", equilibrium.unstable))
+                            .on_hover_text(format!(
+                                "括号内为不稳定方向数，特征值：\n{eigenvalues}"
+                            ));
+                    }
+
+                    if ui.button("跳转").clicked() {
+                        target = Some(equilibrium.y.clone());
+                    }
+                    ui.end_row();
+                }
+            });
+
+        if let Some(mut y) = target {
+            let mut rng = rand::rng();
+            let amplitude = self.data.equilibrium_perturbation;
+            if amplitude > 0.0 {
+                for yi in &mut y {
+                    *yi += rng.random_range(-amplitude..=amplitude);
+                }
+            }
+            self.setting.set_state(&y);
+            self.data
+                .toasts
+                .info("已跳转到平衡点附近")
+                .duration(Some(Duration::from_secs(5)))
+                .show_progress_bar(true);
+        }
+    }
+}
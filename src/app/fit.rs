@@ -0,0 +1,260 @@
+use egui::Rect;
+use num_complex::Complex64;
+
+use super::{FractalPendulumApp, tree};
+
+// 展开这么多层算包围盒，再往下的部分用圆兜住
+const FIT_DEPTH: usize = 10;
+// 取景后树占画面的比例
+const FIT_MARGIN: f64 = 0.9;
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy)]
+pub(super) enum FitMode {
+    Off,
+    // 整棵树的包围盒放在画面中间
+    Tree,
+    // 支点放在画面中心，缩放到装得下整棵树
+    Pivot,
+    // 第几个球放在画面中心，缩放到装得下从它长出去的子树
+    Bob(usize),
+}
+
+// 包围盒，实部虚部分别是两个方向
+#[derive(Clone, Copy)]
+struct Bounds {
+    min: Complex64,
+    max: Complex64,
+}
+
+impl Bounds {
+    fn disc(center: Complex64, radius: f64) -> Self {
+        let r = Complex64::new(radius, radius);
+        Self {
+            min: center - r,
+            max: center + r,
+        }
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            min: Complex64::new(self.min.re.min(other.min.re), self.min.im.min(other.min.im)),
+            max: Complex64::new(self.max.re.max(other.max.re), self.max.im.max(other.max.im)),
+        }
+    }
+
+    // 以center为中心、能装下整个包围盒的半宽和半高
+    fn half_size(self, center: Complex64) -> Complex64 {
+        let a = center - self.min;
+        let b = self.max - center;
+        Complex64::new(a.re.max(b.re), a.im.max(b.im))
+    }
+}
+
+// 从node末端长出去的整棵子树的包围盒。展开几层后，剩下的部分都在以末梢为圆心、
+// |vec|·r/(1 - r)为半径的圆里，r为较大的缩放比例；r不小于1时树无界，只算到递归深度
+fn subtree_bounds(grower: &tree::Grower, node: tree::Node, depth: usize) -> Bounds {
+    let ratio = grower.transforms[0].norm().max(grower.transforms[1].norm());
+    let (depth, tail) = if ratio < 1.0 {
+        (FIT_DEPTH, ratio / (1.0 - ratio))
+    } else {
+        (depth.min(FIT_DEPTH), 0.0)
+    };
+
+    let mut bounds = Bounds::disc(node.start + node.vec, 0.0);
+    let mut nodes = vec![node];
+    for _ in 0..depth {
+        nodes = nodes
+            .iter()
+            .flat_map(|node| {
+                let [t1, t2] = grower.transforms;
+                [node.apply(t1, 0), node.apply(t2, 1)]
+            })
+            .collect();
+        for node in &nodes {
+            bounds = bounds.union(Bounds::disc(node.start + node.vec, 0.0));
+        }
+    }
+    for node in &nodes {
+        bounds = bounds.union(Bounds::disc(node.start + node.vec, node.vec.norm() * tail));
+    }
+    bounds
+}
+
+impl FractalPendulumApp {
+    // 按选定的方式算出画面中心该对准的点和要装下的半宽半高，世界坐标，已经含偏置
+    fn fit_target(&self, rect: Rect) -> Option<(Complex64, Complex64)> {
+        let to_screen = self.to_screen(rect);
+        let depth = self.render_depth();
+        let (grower, root) = self.grower(&self.setting.state(), [0.0; 2], depth, &to_screen);
+        let whole = || subtree_bounds(&grower, root, depth).union(Bounds::disc(root.start, 0.0));
+        match self.setting.fit_mode {
+            FitMode::Off => None,
+            FitMode::Tree => {
+                let bounds = whole();
+                let center = (bounds.min + bounds.max) * 0.5;
+                Some((center, bounds.half_size(center)))
+            }
+            FitMode::Pivot => Some((root.start, whole().half_size(root.start))),
+            FitMode::Bob(i) => {
+                let [t1, t2] = grower.transforms;
+                let node = [root, root.apply(t1, 0), root.apply(t2, 1)][i.min(2)];
+                let center = node.start + node.vec;
+                let bounds = subtree_bounds(&grower, node, depth.saturating_sub(i.min(1)));
+                Some((center, bounds.half_size(center)))
+            }
+        }
+    }
+
+    // 每帧把缩放和偏置往目标平滑地挪一点，缩放按对数插值
+    pub(super) fn auto_fit(&mut self, ctx: &egui::Context, rect: Rect) {
+        if self.setting.spherical {
+            return;
+        }
+        let Some((center, half)) = self.fit_target(rect) else {
+            return;
+        };
+        let short = f64::from(rect.width().min(rect.height()));
+        let zoom = FIT_MARGIN
+            * 0.5
+            * (f64::from(rect.width()) / half.re).min(f64::from(rect.height()) / half.im)
+            / short;
+        if !zoom.is_finite() || !center.re.is_finite() || !center.im.is_finite() {
+            return;
+        }
+
+        // 已经对准时不再改设置，否则渐进渲染每帧都要从头画
+        let current = f64::from(self.setting.zoom);
+        let short_scale = short * current;
+        if center.norm() * short_scale < 0.5 && (zoom / current).ln().abs() < 1e-3 {
+            return;
+        }
+
+        let dt = f64::from(ctx.input(|i| i.stable_dt));
+        let alpha = 1.0 - (-f64::from(self.setting.fit_speed) * dt).exp();
+        self.setting.zoom = (current.ln() + (zoom.ln() - current.ln()) * alpha).exp() as f32;
+        self.setting.x_offset -= center.re * alpha;
+        self.setting.y_offset -= center.im * alpha;
+        ctx.request_repaint();
+    }
+
+    pub(super) fn fit_ui(&mut self, ui: &mut egui::Ui) {
+        let text = |mode: FitMode| match mode {
+            FitMode::Off => "关闭".to_owned(),
+            FitMode::Tree => "整棵树".to_owned(),
+            FitMode::Pivot => "以支点为中心".to_owned(),
+            FitMode::Bob(i) => format!("以球{}为中心", i + 1),
+        };
+        egui::ComboBox::from_id_salt("自动取景选择")
+            .selected_text(text(self.setting.fit_mode))
+            .show_ui(ui, |ui| {
+                for mode in [
+                    FitMode::Off,
+                    FitMode::Tree,
+                    FitMode::Pivot,
+                    FitMode::Bob(0),
+                    FitMode::Bob(1),
+                    FitMode::Bob(2),
+                ] {
+                    ui.selectable_value(&mut self.setting.fit_mode, mode, text(mode));
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::{Pos2, Vec2};
+
+    use super::*;
+
+    fn grower(transforms: [Complex64; 2]) -> tree::Grower {
+        let rect = Rect::from_center_size(Pos2::ZERO, Vec2::splat(2.0));
+        tree::Grower {
+            transforms,
+            depth: 20,
+            view: tree::View::new(&egui::emath::RectTransform::from_to(rect, rect)),
+            hue: [0.0, 1.0],
+            style: [1.0; 3],
+            decay: [1.0; 3],
+            threshold: 0.0,
+        }
+    }
+
+    #[test]
+    fn straight_tree_bounds_are_tight() {
+        // 末梢是1 + 1/2 + 1/4 + …，包围盒正好到2
+        let grower = grower([Complex64::new(0.5, 0.0); 2]);
+        let root = tree::Node::root(Complex64::new(0.0, 0.0), Complex64::new(1.0, 0.0));
+        let bounds = subtree_bounds(&grower, root, grower.depth);
+        assert!((bounds.min.re - 1.0).abs() < 1e-12, "{}", bounds.min.re);
+        assert!((bounds.max.re - 2.0).abs() < 1e-12, "{}", bounds.max.re);
+        assert!(bounds.max.im.abs() < 1e-3 && bounds.min.im.abs() < 1e-3);
+    }
+
+    #[test]
+    fn bounds_cover_deep_leaves() {
+        let grower = grower([Complex64::new(0.5, 0.4), Complex64::new(-0.3, 0.6)]);
+        let root = tree::Node::root(Complex64::new(0.2, -0.1), Complex64::new(0.0, 1.0));
+        let bounds = subtree_bounds(&grower, root, grower.depth);
+        // 比包围盒展开的层数深得多的末梢也都在里面
+        let mut nodes = vec![root];
+        for _ in 0..18 {
+            nodes = nodes
+                .iter()
+                .flat_map(|node| {
+                    [
+                        node.apply(grower.transforms[0], 0),
+                        node.apply(grower.transforms[1], 1),
+                    ]
+                })
+                .collect();
+            for node in &nodes {
+                let end = node.start + node.vec;
+                assert!(
+                    end.re >= bounds.min.re - 1e-12
+                        && end.re <= bounds.max.re + 1e-12
+                        && end.im >= bounds.min.im - 1e-12
+                        && end.im <= bounds.max.im + 1e-12,
+                    "末梢{end}不在包围盒里"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pivot_fit_recovers_the_offset() {
+        let rect = Rect::from_min_size(Pos2::ZERO, Vec2::new(800.0, 600.0));
+        let mut app = FractalPendulumApp::default();
+        app.setting.fit_mode = FitMode::Pivot;
+        app.setting.x_offset = 0.7;
+        app.setting.y_offset = -0.3;
+        let (center, half) = app.fit_target(rect).expect("取景模式没有关");
+        assert!(
+            (center - Complex64::new(0.7, -0.3)).norm() < 1e-12,
+            "{center}"
+        );
+
+        // 反复取景后支点回到画面中心，缩放正好让树占画面的FIT_MARGIN
+        let ctx = egui::Context::default();
+        for _ in 0..2000 {
+            app.auto_fit(&ctx, rect);
+        }
+        let zoom = FIT_MARGIN * 0.5 * (800.0 / half.re).min(600.0 / half.im) / 600.0;
+        let pixel = 1.0 / (600.0 * zoom);
+        assert!(
+            app.setting.x_offset.abs() < pixel,
+            "{}",
+            app.setting.x_offset
+        );
+        assert!(
+            app.setting.y_offset.abs() < pixel,
+            "{}",
+            app.setting.y_offset
+        );
+        assert!(
+            (f64::from(app.setting.zoom) / zoom - 1.0).abs() < 2e-3,
+            "缩放{}，应为{zoom}",
+            app.setting.zoom
+        );
+    }
+}
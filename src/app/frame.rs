@@ -0,0 +1,115 @@
+use egui::{Color32, Pos2, Stroke, Vec2};
+
+use super::{
+    FractalPendulumApp, FractalPendulumAppSetting, Ode,
+    dual::{Dual, DualVec2},
+};
+
+// 摆所在的参考系：重力偏离竖直方向angle，整个参考系以rotation的角速度绕支点转动。
+// 角度都从重力方向量起，所以方程里重力总是朝下，支点、小车、磁铁这些画布上固定的东西反过来转进这个参考系
+#[derive(Clone, Copy)]
+pub(super) struct Frame {
+    angle: f64,
+    rotation: f64,
+}
+
+impl Frame {
+    pub(super) fn new(setting: &FractalPendulumAppSetting) -> Option<Self> {
+        (setting.gravity_angle != 0.0 || setting.frame_rotation != 0.0).then_some(Self {
+            angle: setting.gravity_angle,
+            rotation: setting.frame_rotation,
+        })
+    }
+}
+
+// 把画布上的向量转到重力朝下的参考系
+pub(super) fn rotate_into(angle: f64, [x, y]: [f64; 2]) -> [f64; 2] {
+    let (sin, cos) = angle.sin_cos();
+    [cos * x + sin * y, -sin * x + cos * y]
+}
+
+impl Ode {
+    // 画布上的向量在重力参考系里的分量
+    pub(super) fn to_gravity_frame(&self, w: DualVec2) -> DualVec2 {
+        match self.frame {
+            Some(frame) => {
+                let (sin, cos) = frame.angle.sin_cos();
+                DualVec2::new(w.x * cos + w.y * sin, w.y * cos - w.x * sin)
+            }
+            None => w,
+        }
+    }
+
+    // 位于p处的点随参考系转动的速度Ω×p，离心力和科里奥利力都由它进入拉格朗日量
+    pub(super) fn frame_velocity(&self, p: DualVec2) -> DualVec2 {
+        match self.frame {
+            Some(frame) => p.perp() * frame.rotation,
+            None => DualVec2::default(),
+        }
+    }
+
+    // 小球绕自身转动时参考系额外贡献的角速度
+    pub(super) fn frame_rotation(&self) -> Dual {
+        Dual::constant(self.frame.map_or(0.0, |frame| frame.rotation))
+    }
+}
+
+impl FractalPendulumApp {
+    // 在画布右上角画出重力方向，参考系转动时标出角速度
+    pub(super) fn paint_gravity(&self, painter: &egui::Painter) {
+        if self.setting.gravity_angle == 0.0 && self.setting.frame_rotation == 0.0 {
+            return;
+        }
+        let radius = 16.0;
+        let center = painter.clip_rect().right_top() + Vec2::new(-radius - 12.0, radius + 12.0);
+        let color = Color32::GRAY;
+        painter.circle_stroke(center, radius, Stroke::new(1.0, color));
+
+        let angle = self.setting.gravity_angle as f32;
+        let direction = Vec2::new(-angle.sin(), angle.cos());
+        painter.arrow(
+            center - direction * radius * 0.8,
+            direction * radius * 1.6,
+            Stroke::new(2.0, color),
+        );
+
+        if self.setting.frame_rotation != 0.0 {
+            painter.text(
+                Pos2::new(center.x, center.y + radius + 4.0),
+                egui::Align2::CENTER_TOP,
+                format!("Ω = {:.2}", self.setting.frame_rotation),
+                egui::FontId::monospace(10.0),
+                color,
+            );
+        }
+    }
+
+    pub(super) fn frame_ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("参考系网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("重力方向")
+                    .on_hover_text("重力偏离竖直向下的角度，分形跟着一起转，角度从重力方向量起");
+                ui.add(egui::Slider::new(
+                    &mut self.setting.gravity_angle,
+                    -std::f64::consts::PI..=std::f64::consts::PI,
+                ));
+                ui.end_row();
+
+                ui.label("转动角速度").on_hover_text(
+                    "参考系绕支点转动，带来离心力和科里奥利力，这时守恒的是雅可比积分",
+                );
+                ui.add(
+                    egui::Slider::new(&mut self.setting.frame_rotation, -10.0..=10.0)
+                        .clamping(egui::SliderClamping::Never),
+                );
+                ui.end_row();
+            });
+
+        if ui.button("复位").clicked() {
+            self.setting.gravity_angle = 0.0;
+            self.setting.frame_rotation = 0.0;
+        }
+    }
+}
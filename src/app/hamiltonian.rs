@@ -0,0 +1,226 @@
+use nalgebra::{DMatrix, DVector};
+
+use super::{FractalPendulumApp, FractalPendulumAppSetting, Ode, State};
+
+// 隐式中点法每一步不动点迭代的最多次数
+const MAX_ITERATIONS: usize = 100;
+// 一步不收敛时最多对半拆分的层数
+const MAX_SPLITS: usize = 12;
+
+// 哈密顿形式：状态取坐标x和广义动量p = ∂L/∂v，动能、势能与拉格朗日形式完全相同。
+// 隐式中点法是辛的，能量误差有界不漂移，循环坐标的动量严格不变
+impl Ode {
+    // 质量矩阵M和速度为零时的动量a，p = Mv + a
+    fn momentum_map(&self, x: &[f64], t: f64) -> (DMatrix<f64>, DVector<f64>) {
+        if self.spherical {
+            let y = State::from_iterator(12, x.iter().flat_map(|&x| [x, 0.0]));
+            return (self.spherical_mass_matrix(&y), DVector::zeros(6));
+        }
+        self.generic_momentum_map(x, t)
+    }
+
+    // 状态y对应的广义动量
+    pub(super) fn momenta(&self, t: f64, y: &State) -> DVector<f64> {
+        let n = self.dof();
+        let x: Vec<f64> = (0..n).map(|j| y[2 * j]).collect();
+        let v = DVector::from_fn(n, |j, _| y[2 * j + 1]);
+        let (m, a) = self.momentum_map(&x, t);
+        m * v + a
+    }
+
+    // 由坐标和动量拼出原来的状态，v = M⁻¹(p - a)
+    pub(super) fn state_from_momenta(&self, t: f64, x: &[f64], p: &DVector<f64>) -> Option<State> {
+        let (m, a) = self.momentum_map(x, t);
+        let v = m.cholesky()?.solve(&(p - a));
+        Some(State::from_iterator(
+            2 * x.len(),
+            x.iter().zip(v.iter()).flat_map(|(&x, &v)| [x, v]),
+        ))
+    }
+
+    // 动量的变化率ṗ = ∂L/∂x + 非保守力
+    fn momentum_rate(&self, t: f64, y: &State) -> DVector<f64> {
+        if self.spherical {
+            self.spherical_force(y)
+        } else {
+            self.generic_force(t, y)
+        }
+    }
+
+    // 哈密顿量H = p·v - L，参数不随时间变化时守恒
+    pub(super) fn hamiltonian(&self, t: f64, y: &State) -> f64 {
+        let n = self.dof();
+        let p = self.momenta(t, y);
+        let lagrangian = if self.spherical {
+            self.spherical_lagrangian(y)
+        } else {
+            self.generic_lagrangian(t, y)
+        };
+        (0..n).map(|j| p[j] * y[2 * j + 1]).sum::<f64>() - lagrangian
+    }
+
+    // 隐式中点法z1 = z0 + h·f((z0 + z1)/2)的一步，用不动点迭代求解，不收敛时返回None
+    fn midpoint_step(
+        &self,
+        time: f64,
+        dt: f64,
+        x: &DVector<f64>,
+        p: &DVector<f64>,
+    ) -> Option<(DVector<f64>, DVector<f64>)> {
+        let n = self.dof();
+        let (mut x1, mut p1) = (x.clone(), p.clone());
+        for _ in 0..MAX_ITERATIONS {
+            let xm = (x + &x1) * 0.5;
+            let pm = (p + &p1) * 0.5;
+            let ym = self.state_from_momenta(time + 0.5 * dt, xm.as_slice(), &pm)?;
+            let v = DVector::from_fn(n, |j, _| ym[2 * j + 1]);
+            let x_next = x + v * dt;
+            let p_next = p + self.momentum_rate(time + 0.5 * dt, &ym) * dt;
+            let change = (&x_next - &x1).norm() + (&p_next - &p1).norm();
+            x1 = x_next;
+            p1 = p_next;
+            if !change.is_finite() {
+                return None;
+            }
+            if change <= 1e-13 * (1.0 + x1.norm() + p1.norm()) {
+                return Some((x1, p1));
+            }
+        }
+        None
+    }
+
+    // 不收敛时把这一步对半拆开再试。只在靠近奇点、速度很大时发生，拆开的那几步不再严格保辛
+    fn split_step(
+        &self,
+        time: f64,
+        dt: f64,
+        x: &DVector<f64>,
+        p: &DVector<f64>,
+        splits: usize,
+    ) -> Option<(DVector<f64>, DVector<f64>)> {
+        self.midpoint_step(time, dt, x, p).or_else(|| {
+            let splits = splits.checked_sub(1)?;
+            let half = 0.5 * dt;
+            let (x, p) = self.split_step(time, half, x, p, splits)?;
+            self.split_step(time + half, half, &x, &p, splits)
+        })
+    }
+
+    // 固定步长的辛积分，返回每一步的结果
+    pub(super) fn symplectic_trajectory(
+        &self,
+        y: State,
+        t0: f64,
+        t: f64,
+        h: f64,
+    ) -> Option<Vec<State>> {
+        let n = self.dof();
+        let steps = (t / h).ceil().max(1.0) as usize;
+        let dt = t / steps as f64;
+
+        let mut x = DVector::from_fn(n, |j, _| y[2 * j]);
+        let mut p = self.momenta(t0, &y);
+        let mut trajectory = vec![y];
+        for step in 0..steps {
+            (x, p) = self.split_step(t0 + step as f64 * dt, dt, &x, &p, MAX_SPLITS)?;
+            trajectory.push(self.state_from_momenta(
+                t0 + (step + 1) as f64 * dt,
+                x.as_slice(),
+                &p,
+            )?);
+        }
+        Some(trajectory)
+    }
+}
+
+impl FractalPendulumAppSetting {
+    // 各广义坐标的名字，顺序与状态一致
+    fn coordinate_names(&self) -> Vec<&'static str> {
+        let mut names = vec!["θ1", "θ2", "θ3"];
+        if self.spherical {
+            names.extend(["φ1", "φ2", "φ3"]);
+            return names;
+        }
+        if self.elastic {
+            names.extend(["s1", "s2", "s3"]);
+        }
+        if self.cart {
+            names.push("X");
+        }
+        names
+    }
+}
+
+impl FractalPendulumApp {
+    pub(super) fn hamiltonian_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.setting.hamiltonian, "启用").on_hover_text(
+            "用广义动量代替速度，按隐式中点法以固定步长h做辛积分，长时间积分能量也不漂移。开启约束时仍用原来的积分器",
+        );
+        if !self.setting.hamiltonian {
+            return;
+        }
+
+        // 方程里的时间从当前的模拟时间setting.time算起，调制和支点驱动都按它建好，当前时刻就是方程里的0
+        let ode = self.ode();
+        let now = 0.0;
+        let y = self.setting.state();
+        let mut p = ode.momenta(now, &y);
+        let mut changed = false;
+        egui::Grid::new("哈密顿网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                for (j, name) in self.setting.coordinate_names().into_iter().enumerate() {
+                    ui.label(format!("p({name})"));
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut p[j]).speed(0.01))
+                        .changed();
+                    ui.end_row();
+                }
+
+                ui.label("H")
+                    .on_hover_text("哈密顿量p·v - L，参数不随时间变化且没有阻力时守恒");
+                ui.label(format!("{:.9}", ode.hamiltonian(now, &y)));
+                ui.end_row();
+            });
+
+        // 输入的是动量，换算回速度保存
+        if changed {
+            let x: Vec<f64> = (0..ode.dof()).map(|j| y[2 * j]).collect();
+            if let Some(y) = ode.state_from_momenta(now, &x, &p) {
+                self.setting.set_state(&y);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symplectic_energy_drift_is_bounded() {
+        // 小幅摆动，周期约2秒，积分五十多个周期
+        let setting = FractalPendulumAppSetting {
+            q: [0.3, 0.0, -0.2, 0.0, 0.1, 0.0],
+            hamiltonian: true,
+            ..Default::default()
+        };
+        let ode = Ode::new(&setting);
+        assert!(ode.symplectic && ode.conserves_energy());
+
+        let y = setting.state();
+        let energy = ode.hamiltonian(0.0, &y);
+        let trajectory = ode
+            .symplectic_trajectory(y, 0.0, 100.0, 0.005)
+            .expect("积分不应出错");
+        let drift = trajectory
+            .iter()
+            .map(|y| (ode.hamiltonian(0.0, y) - energy).abs())
+            .fold(0.0, f64::max);
+        assert!(
+            drift <= 1e-6 * energy.abs(),
+            "能量{energy}最多偏离了{drift}"
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use nalgebra::{DMatrix, DVector};
+
+use super::{
+    Ode, State,
+    dual::{Dual, DualVec2},
+};
+
+// 原来的解析式只适用于刚性三摆，其余变体都由拉格朗日量直接求出运动方程：
+// d/dt(∂L/∂v) - ∂L/∂x = 0，其中的导数全部用对偶数精确求出
+impl Ode {
+    // 广义坐标的个数：三个角度，弹性杆时再加上三根杆的伸长量，有小车时最后是小车的位置，球面摆时为六个角度
+    pub(super) fn dof(&self) -> usize {
+        if self.spherical {
+            return 6;
+        }
+        let elastic = if self.stiffness.is_some() { 3 } else { 0 };
+        let cart = usize::from(self.cart.is_some());
+        3 + elastic + cart
+    }
+
+    // 三根臂的长度
+    fn arm_lengths(&self, x: &[Dual], t: Dual) -> [Dual; 3] {
+        std::array::from_fn(|k| match self.stiffness {
+            Some(_) => x[3 + k] + self.length(k, t),
+            None => self.length(k, t),
+        })
+    }
+
+    // 三根臂的绝对角度，θ2、θ3是相对第一根臂的角度
+    fn arm_angles(x: &[Dual]) -> [Dual; 3] {
+        [x[0], x[0] + x[1], x[0] + x[2]]
+    }
+
+    // 随支点平动的参考系里的等效重力，支点加速时加上惯性力-ma
+    fn gravity(&self, t: Dual) -> DualVec2 {
+        // 画图坐标系的y轴朝下
+        let gravity = DualVec2::new(Dual::default(), self.gravity_strength(t));
+        match self.pivot {
+            Some(pivot) => gravity - self.to_gravity_frame(pivot.acceleration(t)),
+            None => gravity,
+        }
+    }
+
+    // 三个小球相对支点的位置，与画图时的坐标一致
+    pub(super) fn positions(&self, x: &[Dual], t: Dual) -> [DualVec2; 3] {
+        let r = self.arm_lengths(x, t);
+        let a = Self::arm_angles(x);
+        let p1 = DualVec2::direction(a[0]) * r[0];
+        [
+            p1,
+            p1 + DualVec2::direction(a[1]) * r[1],
+            p1 + DualVec2::direction(a[2]) * r[2],
+        ]
+    }
+
+    // 小车相对轨道原点的位移，轨道在画布上沿水平方向
+    pub(super) fn cart_displacement(&self, x: &[Dual]) -> DualVec2 {
+        match self.cart_index() {
+            Some(i) => self.to_gravity_frame(DualVec2::new(x[i], Dual::default())),
+            None => DualVec2::default(),
+        }
+    }
+
+    // 动能各项的权重、相对参考系的速度和参考系转动带来的速度，动能为Σ½w|c + s|²。
+    // 沿用原来动能公式的约定：每根臂只按自己的角速度转动，臂k贡献ρ̇k·u + ρk·ωk·n
+    // 参数随时间变化时权重和速度都显含时间
+    fn frame_terms(&self, x: &[Dual], v: &[Dual], t: Dual) -> Vec<(Dual, DualVec2, DualVec2)> {
+        let r = self.arm_lengths(x, t);
+        let a = Self::arm_angles(x);
+        let m: [Dual; 3] = std::array::from_fn(|k| self.mass(k, t));
+        let arm = |k: usize| {
+            let u = DualVec2::direction(a[k]);
+            let stretch_rate = if self.stiffness.is_some() {
+                v[3 + k]
+            } else {
+                Dual::default()
+            };
+            u * (stretch_rate + self.length_rate(k, t)) + u.perp() * (r[k] * v[k])
+        };
+
+        // 支点随小车沿轨道运动
+        let base = match self.cart_index() {
+            Some(i) => self.to_gravity_frame(DualVec2::new(v[i], Dual::default())),
+            None => DualVec2::default(),
+        };
+        let d = self.cart_displacement(x);
+        let p = self.positions(x, t);
+        let spin = |p: DualVec2| self.frame_velocity(d + p);
+
+        let v1 = base + arm(0);
+        let mut terms = vec![
+            (m[0], v1, spin(p[0])),
+            (m[1], v1 + arm(1), spin(p[1])),
+            (m[2], v1 + arm(2), spin(p[2])),
+        ];
+        if let Some(cart) = &self.cart {
+            terms.push((Dual::constant(cart.mass), base, spin(DualVec2::default())));
+        }
+
+        if let Some(compound) = self.compound {
+            // 杆上的速度沿杆线性分布，积分后为质心平动加上1/12倍的两端相对速度
+            let start = [base, v1, v1];
+            let start_position = [DualVec2::default(), p[0], p[0]];
+            for (k, (start, mass)) in start.into_iter().zip(compound.rod_mass).enumerate() {
+                let w = arm(k);
+                let rod = p[k] - start_position[k];
+                terms.push((
+                    Dual::constant(mass),
+                    start + w * 0.5,
+                    spin(start_position[k] + rod * 0.5),
+                ));
+                terms.push((Dual::constant(mass / 12.0), w, self.frame_velocity(rod)));
+            }
+            // 小球随所在的臂一起转动，转动惯量为½mR²
+            for ((m, r), omega) in m.into_iter().zip(compound.bob_size).zip(v) {
+                terms.push((
+                    m * (0.5 * r * r),
+                    DualVec2::new(*omega, Dual::default()),
+                    DualVec2::new(self.frame_rotation(), Dual::default()),
+                ));
+            }
+        }
+        terms
+    }
+
+    // 动能各项的权重和对应的速度，动能为Σ½w|c|²
+    fn kinetic_terms(&self, x: &[Dual], v: &[Dual], t: Dual) -> Vec<(Dual, DualVec2)> {
+        self.frame_terms(x, v, t)
+            .into_iter()
+            .map(|(w, c, s)| (w, c + s))
+            .collect()
+    }
+
+    // 势能：重力加上弹簧
+    fn potential(&self, x: &[Dual], t: Dual) -> Dual {
+        let gravity = self.gravity(t);
+        let p = self.positions(x, t);
+        let m: [Dual; 3] = std::array::from_fn(|k| self.mass(k, t));
+        let mut v = -(p[0] * m[0] + p[1] * m[1] + p[2] * m[2]).dot(gravity);
+        if let Some(compound) = self.compound {
+            // 杆的重心在中点
+            let centers = [p[0] * 0.5, (p[0] + p[1]) * 0.5, (p[0] + p[2]) * 0.5];
+            for (center, mass) in centers.into_iter().zip(compound.rod_mass) {
+                v = v - center.dot(gravity) * mass;
+            }
+        }
+        if let Some(stiffness) = self.stiffness {
+            for k in 0..3 {
+                v = v + x[3 + k] * x[3 + k] * (0.5 * stiffness[k]);
+            }
+        }
+        if let Some(cart) = &self.cart {
+            // 小车带着整个摆沿轨道移动，只有等效重力沿轨道的分量做功
+            let rods = self.compound.map_or(0.0, |c| c.rod_mass.iter().sum());
+            let total = m[0] + m[1] + m[2] + cart.mass + rods;
+            v = v - self.cart_displacement(x).dot(gravity) * total;
+        }
+        v + self.magnet_potential(x, t)
+    }
+
+    fn lagrangian(&self, x: &[Dual], v: &[Dual], t: Dual) -> Dual {
+        let kinetic = self
+            .kinetic_terms(x, v, t)
+            .into_iter()
+            .fold(Dual::default(), |sum, (w, c)| {
+                sum + c.norm_squared() * w * 0.5
+            });
+        kinetic - self.potential(x, t)
+    }
+
+    // 把状态拆成坐标和速度
+    fn split(&self, y: &State) -> (Vec<f64>, Vec<f64>) {
+        (0..self.dof()).map(|j| (y[2 * j], y[2 * j + 1])).unzip()
+    }
+
+    // 各项的权重，以及速度对广义速度的导数矩阵A的各列。
+    // 速度一般形如c = Av + b，第j列由第j个广义速度取1时的速度减去全取0时的速度得到
+    fn velocity_columns(&self, x: &[Dual], t: Dual) -> (Vec<Dual>, Vec<Vec<DualVec2>>) {
+        let n = self.dof();
+        let (weights, base): (Vec<Dual>, Vec<DualVec2>) = self
+            .kinetic_terms(x, &vec![Dual::default(); n], t)
+            .into_iter()
+            .unzip();
+        let columns = (0..n)
+            .map(|j| {
+                let e: Vec<Dual> = (0..n)
+                    .map(|i| Dual::constant(if i == j { 1.0 } else { 0.0 }))
+                    .collect();
+                self.kinetic_terms(x, &e, t)
+                    .into_iter()
+                    .zip(&base)
+                    .map(|((_, c), &b)| c - b)
+                    .collect()
+            })
+            .collect();
+        (weights, columns)
+    }
+
+    // 由各列拼出质量矩阵
+    fn mass_matrix_from(weights: &[Dual], columns: &[Vec<DualVec2>]) -> DMatrix<f64> {
+        let n = columns.len();
+        DMatrix::from_fn(n, n, |i, j| {
+            weights
+                .iter()
+                .enumerate()
+                .map(|(k, w)| w.v * columns[i][k].dot(columns[j][k]).v)
+                .sum()
+        })
+    }
+
+    // 质量矩阵，动能的二次部分为½vᵀMv
+    pub(super) fn generic_mass_matrix(&self, y: &State) -> DMatrix<f64> {
+        let (x, _) = self.split(y);
+        let x: Vec<Dual> = x.into_iter().map(Dual::constant).collect();
+        let (weights, columns) = self.velocity_columns(&x, Dual::default());
+        Self::mass_matrix_from(&weights, &columns)
+    }
+
+    // 广义加速度。∂L/∂v = Σw·Aᵀc，沿运动方向对它求导时w、A和c都要求导
+    pub(super) fn generic_acceleration(&self, t: f64, y: &State) -> Option<DVector<f64>> {
+        let n = self.dof();
+        let (x, v) = self.split(y);
+
+        // 坐标的导数取为速度，于是对偶部分就是沿运动方向的时间导数
+        let x_moving: Vec<Dual> = x.iter().zip(&v).map(|(&x, &v)| Dual::new(x, v)).collect();
+        let v_constant: Vec<Dual> = v.iter().copied().map(Dual::constant).collect();
+        // 参数显含时间时，时间本身也随之前进
+        let t_moving = Dual::new(t, 1.0);
+
+        let (weights, columns) = self.velocity_columns(&x_moving, t_moving);
+        let m = Self::mass_matrix_from(&weights, &columns);
+        let current = self.kinetic_terms(&x_moving, &v_constant, t_moving);
+
+        let mut rhs = -self.magnet_friction(&x_moving, t_moving, &columns, &current);
+        for j in 0..n {
+            // d/dt(∂L/∂vj)在加速度为零时的值
+            let momentum_rate: f64 = weights
+                .iter()
+                .zip(&current)
+                .enumerate()
+                .map(|(k, (w, (_, c)))| (*w * columns[j][k].dot(*c)).d)
+                .sum();
+
+            // ∂L/∂xj
+            let x_seeded: Vec<Dual> = x
+                .iter()
+                .enumerate()
+                .map(|(i, &x)| Dual::new(x, if i == j { 1.0 } else { 0.0 }))
+                .collect();
+            let force = self.lagrangian(&x_seeded, &v_constant, Dual::constant(t)).d;
+
+            rhs[j] += force - momentum_rate;
+        }
+        if let Some(i) = self.cart_index() {
+            // 控制器的推力是作用在小车坐标上的广义力
+            rhs[i] += self.control_force(y);
+        }
+
+        Some(m.cholesky()?.solve(&rhs))
+    }
+
+    // 质量矩阵M和速度全为零时的动量a，广义动量为p = Mv + a
+    pub(super) fn generic_momentum_map(&self, x: &[f64], t: f64) -> (DMatrix<f64>, DVector<f64>) {
+        let n = self.dof();
+        let x: Vec<Dual> = x.iter().copied().map(Dual::constant).collect();
+        let t = Dual::constant(t);
+        let (weights, columns) = self.velocity_columns(&x, t);
+        let base = self.kinetic_terms(&x, &vec![Dual::default(); n], t);
+        let offset = DVector::from_fn(n, |j, _| {
+            weights
+                .iter()
+                .zip(&base)
+                .enumerate()
+                .map(|(k, (w, (_, b)))| w.v * columns[j][k].dot(*b).v)
+                .sum()
+        });
+        (Self::mass_matrix_from(&weights, &columns), offset)
+    }
+
+    // 广义力：∂L/∂x加上阻力和控制力，哈密顿形式下就是动量的变化率
+    pub(super) fn generic_force(&self, t: f64, y: &State) -> DVector<f64> {
+        let n = self.dof();
+        let (x, v) = self.split(y);
+        let v_constant: Vec<Dual> = v.iter().copied().map(Dual::constant).collect();
+        let mut force = DVector::from_fn(n, |j, _| {
+            let x_seeded: Vec<Dual> = x
+                .iter()
+                .enumerate()
+                .map(|(i, &x)| Dual::new(x, if i == j { 1.0 } else { 0.0 }))
+                .collect();
+            self.lagrangian(&x_seeded, &v_constant, Dual::constant(t)).d
+        });
+
+        if self.magnets.is_some() {
+            let x: Vec<Dual> = x.into_iter().map(Dual::constant).collect();
+            let t = Dual::constant(t);
+            let (_, columns) = self.velocity_columns(&x, t);
+            let current = self.kinetic_terms(&x, &v_constant, t);
+            force -= self.magnet_friction(&x, t, &columns, &current);
+        }
+        if let Some(i) = self.cart_index() {
+            force[i] += self.control_force(y);
+        }
+        force
+    }
+
+    // 磁铁摆里最后一个球受到的阻力-γṗ3，对应的广义力为-γ(∂ṗ3/∂vj)·ṗ3，速度相对随参考系转动的磁铁。
+    // columns和current是同一点处velocity_columns和kinetic_terms的结果，只用到值部分
+    fn magnet_friction(
+        &self,
+        x: &[Dual],
+        t: Dual,
+        columns: &[Vec<DualVec2>],
+        current: &[(Dual, DualVec2)],
+    ) -> DVector<f64> {
+        let Some(magnets) = &self.magnets else {
+            return DVector::zeros(self.dof());
+        };
+        let relative_velocity = current[2].1 - self.frame_velocity(self.last_bob(x, t));
+        DVector::from_fn(self.dof(), |j, _| {
+            magnets.friction * columns[j][2].dot(relative_velocity).v
+        })
+    }
+
+    pub(super) fn generic_lagrangian(&self, t: f64, y: &State) -> f64 {
+        let (x, v) = self.split(y);
+        let x: Vec<Dual> = x.into_iter().map(Dual::constant).collect();
+        let v: Vec<Dual> = v.into_iter().map(Dual::constant).collect();
+        self.lagrangian(&x, &v, Dual::constant(t)).v
+    }
+
+    // 积分起点处的动能和势能。参考系转动时守恒的是雅可比积分，
+    // 动能只算相对参考系的部分，参考系转动带来的那部分作为离心势能记到势能里
+    pub(super) fn generic_energy(&self, y: &State) -> (f64, f64) {
+        let (x, v) = self.split(y);
+        let x: Vec<Dual> = x.into_iter().map(Dual::constant).collect();
+        let v: Vec<Dual> = v.into_iter().map(Dual::constant).collect();
+        let t = Dual::default();
+        let (kinetic, centrifugal) = self
+            .frame_terms(&x, &v, t)
+            .into_iter()
+            .map(|(w, c, s)| {
+                (
+                    0.5 * w.v * c.norm_squared().v,
+                    0.5 * w.v * s.norm_squared().v,
+                )
+            })
+            .fold((0.0, 0.0), |(a, b), (c, d)| (a + c, b + d));
+        (kinetic, self.potential(&x, t).v - centrifugal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng as _, SeedableRng as _, rngs::StdRng};
+
+    use super::*;
+    use crate::app::FractalPendulumAppSetting;
+
+    #[test]
+    fn generic_acceleration_matches_classic() {
+        let ode = Ode::new(&FractalPendulumAppSetting::default());
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..8 {
+            let y = State::from_fn(6, |i, _| {
+                if i % 2 == 0 {
+                    rng.random_range(-3.0..3.0)
+                } else {
+                    rng.random_range(-5.0..5.0)
+                }
+            });
+            let mut dy = State::zeros(6);
+            ode.classic_system(&y, &mut dy);
+            let a = ode
+                .generic_acceleration(0.0, &y)
+                .expect("刚性三摆的质量矩阵不应奇异");
+            for j in 0..3 {
+                let expected = dy[2 * j + 1];
+                assert!(
+                    (a[j] - expected).abs() <= 1e-9 * expected.abs().max(1.0),
+                    "状态{y}下第{j}个加速度为{}，解析式为{expected}",
+                    a[j]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn generic_energy_is_conserved() {
+        // 弹性杆加复摆，全部由拉格朗日量求出，没有阻力也不显含时间
+        let setting = FractalPendulumAppSetting {
+            elastic: true,
+            compound: true,
+            bob_size: [0.1; 3],
+            ..Default::default()
+        };
+        let ode = Ode::new(&setting);
+        assert!(!ode.is_classic() && ode.conserves_energy());
+
+        let y = setting.state();
+        let energy = |y: &State| {
+            let (t, v) = ode.energy(y);
+            t + v
+        };
+        let end = ode.flow(y.clone(), 0.0, 2.0, 0.01).expect("积分不应出错");
+        let (before, after) = (energy(&y), energy(&end));
+        assert!(
+            (after - before).abs() <= 1e-8 * before.abs().max(1.0),
+            "能量从{before}变成了{after}"
+        );
+    }
+}
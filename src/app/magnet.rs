@@ -0,0 +1,442 @@
+use std::f64::consts::{PI, TAU};
+
+use egui::{Color32, Rect, Shape, Vec2};
+use num_complex::Complex64;
+
+use super::{
+    FractalPendulumApp, FractalPendulumAppSetting, Ode, State,
+    cart::Controller,
+    dual::{Dual, DualVec2},
+    frame::rotate_into,
+    hsl_to_rgb, tree,
+};
+
+// 磁铁摆：最后一个球被平面上固定的几块磁铁吸引，同时受到与速度成正比的阻力
+#[derive(Clone)]
+pub(super) struct Magnets {
+    // 磁铁相对支点的位置
+    positions: Vec<[f64; 2]>,
+    strength: f64,
+    // 磁铁到摆动平面的距离，避免势能在磁铁正上方发散
+    height: f64,
+    pub(super) friction: f64,
+}
+
+impl Magnets {
+    pub(super) fn new(setting: &FractalPendulumAppSetting) -> Option<Self> {
+        setting.magnetic.then(|| Self {
+            // 方程在重力朝下的参考系里写出，磁铁的位置也转过去
+            positions: magnet_positions(setting)
+                .into_iter()
+                .map(|p| rotate_into(setting.gravity_angle, p))
+                .collect(),
+            strength: setting.magnet_strength,
+            height: setting.magnet_height,
+            friction: setting.magnet_friction,
+        })
+    }
+
+    // 离p最近的磁铁
+    fn nearest(&self, p: [f64; 2]) -> Option<(usize, f64)> {
+        self.positions
+            .iter()
+            .map(|m| (m[0] - p[0]).hypot(m[1] - p[1]))
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+}
+
+// 磁铁均匀分布在以最后一个球的静止位置为圆心的圆上
+fn magnet_positions(setting: &FractalPendulumAppSetting) -> Vec<[f64; 2]> {
+    let center = setting.l[0] + setting.l[2];
+    let count = setting.magnet_count;
+    (0..count)
+        .map(|i| {
+            let angle = TAU * i as f64 / count as f64 - PI / 2.0;
+            [
+                setting.magnet_radius * angle.cos(),
+                center + setting.magnet_radius * angle.sin(),
+            ]
+        })
+        .collect()
+}
+
+// 每演化这么久检查一次最后一个球是否停下
+const SETTLE_CHECK: f64 = 0.5;
+// 最后一个球的速率低于此值算作慢下来。第二根臂没有阻力，会一直带着它小幅抖动，所以不取得太小
+const SETTLE_SPEED: f64 = 0.2;
+// 连续这么多次检查都慢下来、且离同一块磁铁最近才算停下，免得把摆动的最高点当成停下
+const SETTLE_CHECKS: usize = 6;
+
+fn magnet_color(i: usize, count: usize) -> Color32 {
+    hsl_to_rgb(std::f32::consts::TAU * i as f32 / count as f32, 0.8, 0.5)
+}
+
+impl Ode {
+    // 最后一个球的位置，装在小车上时加上小车的位移
+    pub(super) fn last_bob(&self, x: &[Dual], t: Dual) -> DualVec2 {
+        self.positions(x, t)[2] + self.cart_displacement(x)
+    }
+
+    // 各磁铁的势能之和，随距离按-s/√(d²+h²)变化
+    pub(super) fn magnet_potential(&self, x: &[Dual], t: Dual) -> Dual {
+        let Some(magnets) = &self.magnets else {
+            return Dual::default();
+        };
+        let p = self.last_bob(x, t);
+        magnets
+            .positions
+            .iter()
+            .fold(Dual::default(), |sum, &[mx, my]| {
+                let d = p - DualVec2::new(Dual::constant(mx), Dual::constant(my));
+                let r = (d.norm_squared() + magnets.height * magnets.height).sqrt();
+                sum - Dual::constant(magnets.strength) / r
+            })
+    }
+
+    // 最后一个球在t时刻的位置和速率，对偶部分取各广义速度，求出的就是速度
+    fn last_bob_motion(&self, t: f64, y: &State) -> ([f64; 2], f64) {
+        let x: Vec<Dual> = (0..self.dof())
+            .map(|j| Dual::new(y[2 * j], y[2 * j + 1]))
+            .collect();
+        let p = self.last_bob(&x, Dual::new(t, 1.0));
+        ([p.x.v, p.y.v], p.x.d.hypot(p.y.d))
+    }
+
+    // 最后一个球离哪块磁铁最近。重力让球停在磁铁和中心之间，所以不要求正好在磁铁上
+    fn nearest_magnet(&self, t: f64, y: &State) -> Option<usize> {
+        let magnets = self.magnets.as_ref()?;
+        magnets
+            .nearest(self.last_bob_motion(t, y).0)
+            .map(|(i, _)| i)
+    }
+}
+
+// 一次吸引域计算需要的全部参数，可以整个交给后台线程
+struct Basin {
+    setting: FractalPendulumAppSetting,
+    controller: Option<Controller>,
+    resolution: usize,
+    duration: f64,
+}
+
+impl Basin {
+    // 第row行：θ3从π到-π，每行θ1从-π到π，其余变量取当前值，速度全为零
+    fn row(&self, row: usize) -> (usize, Vec<Option<usize>>) {
+        let mut ode = Ode::new(&self.setting);
+        ode.attach_controller(self.controller.as_ref());
+        let mut y = self.setting.state();
+        for j in 0..ode.dof() {
+            y[2 * j + 1] = 0.0;
+        }
+        let step = TAU / self.resolution as f64;
+        let theta3 = PI - (row as f64 + 0.5) * step;
+        let cells = (0..self.resolution)
+            .map(|column| {
+                let mut y = y.clone();
+                y[0] = -PI + (column as f64 + 0.5) * step;
+                y[4] = theta3;
+                self.settle(&ode, y)
+            })
+            .collect();
+        (row, cells)
+    }
+
+    // 一段一段往下积，直到最后一个球停在某块磁铁旁，演化时长用完还没停下的格子算作未定
+    fn settle(&self, ode: &Ode, mut y: State) -> Option<usize> {
+        let mut time = 0.0;
+        let mut slow = 0;
+        let mut last = None;
+        while time < self.duration {
+            let dt = SETTLE_CHECK.min(self.duration - time);
+            y = ode.flow(y, time, dt, self.setting.h)?;
+            time += dt;
+            let nearest = ode.nearest_magnet(time, &y);
+            if ode.last_bob_motion(time, &y).1 < SETTLE_SPEED && nearest == last {
+                slow += 1;
+                if slow >= SETTLE_CHECKS {
+                    return nearest;
+                }
+            } else {
+                slow = 0;
+            }
+            last = nearest;
+        }
+        None
+    }
+}
+
+// 正在进行的计算。原生平台按核数开后台线程隔行分担，网页上每帧算一行
+#[cfg(not(target_arch = "wasm32"))]
+struct Job {
+    receiver: std::sync::mpsc::Receiver<(usize, Vec<Option<usize>>)>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Job {
+    fn start(basin: Basin) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let basin = std::sync::Arc::new(basin);
+        let workers = std::thread::available_parallelism().map_or(1, usize::from);
+        for worker in 0..workers {
+            let basin = basin.clone();
+            let sender = sender.clone();
+            // 接收端被丢弃后发送失败，线程随之退出
+            std::thread::spawn(move || {
+                for row in (worker..basin.resolution).step_by(workers) {
+                    if sender.send(basin.row(row)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Self { receiver }
+    }
+
+    fn poll(&self) -> Vec<(usize, Vec<Option<usize>>)> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+struct Job {
+    basin: Basin,
+    next: usize,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Job {
+    fn start(basin: Basin) -> Self {
+        Self { basin, next: 0 }
+    }
+
+    fn poll(&mut self) -> Vec<(usize, Vec<Option<usize>>)> {
+        if self.next < self.basin.resolution {
+            self.next += 1;
+            vec![self.basin.row(self.next - 1)]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+pub(super) struct MagnetData {
+    resolution: usize,
+    duration: f64,
+    // 每个格子最后停在哪块磁铁旁，None为计算出错、没有停下或尚未算出
+    cells: Vec<Vec<Option<usize>>>,
+    count: usize,
+    finished: usize,
+    job: Option<Job>,
+    open: bool,
+}
+
+impl Default for MagnetData {
+    fn default() -> Self {
+        Self {
+            resolution: 32,
+            duration: 60.0,
+            cells: Vec::new(),
+            count: 0,
+            finished: 0,
+            job: None,
+            open: false,
+        }
+    }
+}
+
+impl FractalPendulumApp {
+    // 在画布上标出磁铁。磁铁和方程里一样相对支点固定，不随小车移动
+    pub(super) fn paint_magnets(
+        &self,
+        painter: &egui::Painter,
+        to_screen: &egui::emath::RectTransform,
+    ) {
+        if !self.setting.magnetic {
+            return;
+        }
+        let [px, py] = self.pivot_position();
+        let x = self.setting.x_offset + px;
+        let y = self.setting.y_offset + py;
+        let view = tree::View::new(to_screen);
+        let count = self.setting.magnet_count;
+        for (i, [mx, my]) in magnet_positions(&self.setting).into_iter().enumerate() {
+            painter.circle_filled(
+                view.apply(Complex64::new(x + mx, y + my)),
+                4.0,
+                magnet_color(i, count),
+            );
+        }
+    }
+
+    pub(super) fn magnet_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.setting.magnetic, "启用")
+            .on_hover_text("最后一个球被平面上的几块磁铁吸引，并受到阻力");
+        if !self.setting.magnetic {
+            return;
+        }
+
+        egui::Grid::new("磁铁网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("数量");
+                ui.add(egui::DragValue::new(&mut self.setting.magnet_count).range(1..=12));
+                ui.end_row();
+
+                ui.label("半径").on_hover_text("磁铁所在圆的半径");
+                ui.add(
+                    egui::DragValue::new(&mut self.setting.magnet_radius)
+                        .speed(0.01)
+                        .range(0.0..=10.0),
+                );
+                ui.end_row();
+
+                ui.label("强度");
+                ui.add(
+                    egui::DragValue::new(&mut self.setting.magnet_strength)
+                        .speed(0.01)
+                        .range(0.0..=100.0),
+                );
+                ui.end_row();
+
+                ui.label("高度").on_hover_text("磁铁到摆动平面的距离");
+                ui.add(
+                    egui::DragValue::new(&mut self.setting.magnet_height)
+                        .speed(0.01)
+                        .range(0.01..=10.0),
+                );
+                ui.end_row();
+
+                ui.label("阻力");
+                ui.add(
+                    egui::DragValue::new(&mut self.setting.magnet_friction)
+                        .speed(0.01)
+                        .range(0.0..=100.0),
+                );
+                ui.end_row();
+            });
+
+        // 按钮里要用，先取出来，后面data一直借着self
+        let controller = self.controller().cloned();
+        let data = &mut self.data.magnet;
+        egui::Grid::new("吸引域网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("分辨率");
+                ui.add(egui::DragValue::new(&mut data.resolution).range(2..=256));
+                ui.end_row();
+
+                ui.label("最长演化时长").on_hover_text(
+                    "每个起点演化到最后一个球停下，再看它离哪块磁铁最近；到这么久还没停下的格子留空",
+                );
+                ui.add(
+                    egui::DragValue::new(&mut data.duration)
+                        .speed(0.1)
+                        .range(0.1..=1000.0),
+                );
+                ui.end_row();
+            });
+
+        ui.horizontal(|ui| {
+            if ui
+                .button("吸引域")
+                .on_hover_text("从静止开始，横轴为θ1，纵轴为θ3，其余变量取当前值")
+                .clicked()
+            {
+                data.cells = vec![vec![None; data.resolution]; data.resolution];
+                data.count = self.setting.magnet_count;
+                data.finished = 0;
+                data.job = Some(Job::start(Basin {
+                    setting: self.setting.clone(),
+                    controller: controller.clone(),
+                    resolution: data.resolution,
+                    duration: data.duration,
+                }));
+                data.open = true;
+            }
+
+            if data.job.is_some() && ui.button("停止").clicked() {
+                data.job = None;
+            }
+
+            if ui.button("显示").clicked() {
+                data.open = true;
+            }
+        });
+    }
+
+    // 每帧都要调用，收集后台结果并画出吸引域窗口
+    pub(super) fn magnet_window(&mut self, ctx: &egui::Context) {
+        let data = &mut self.data.magnet;
+
+        if let Some(job) = &mut data.job {
+            for (row, cells) in job.poll() {
+                data.cells[row] = cells;
+                data.finished += 1;
+            }
+            if data.finished >= data.cells.len() {
+                data.job = None;
+            } else {
+                ctx.request_repaint();
+            }
+        }
+
+        let mut open = data.open;
+        egui::Window::new("吸引域")
+            .open(&mut open)
+            .default_size([300.0, 300.0])
+            .show(ctx, |ui| {
+                if data.job.is_some() {
+                    ui.add(
+                        egui::ProgressBar::new(
+                            data.finished as f32 / data.cells.len().max(1) as f32,
+                        )
+                        .show_percentage(),
+                    );
+                }
+                plot(ui, &data.cells, data.count);
+            });
+        data.open = open;
+    }
+}
+
+fn plot(ui: &mut egui::Ui, cells: &[Vec<Option<usize>>], count: usize) {
+    let side = ui.available_width().min(ui.available_height().max(200.0));
+    let (response, painter) = ui.allocate_painter(Vec2::splat(side), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+
+    let size = rect.width() / cells.len().max(1) as f32;
+    painter.extend(cells.iter().enumerate().flat_map(|(row, line)| {
+        line.iter().enumerate().filter_map(move |(column, cell)| {
+            cell.map(|i| {
+                Shape::rect_filled(
+                    Rect::from_min_size(
+                        rect.min + Vec2::new(column as f32, row as f32) * size,
+                        Vec2::splat(size),
+                    ),
+                    0.0,
+                    magnet_color(i, count),
+                )
+            })
+        })
+    }));
+
+    let font = egui::FontId::monospace(10.0);
+    painter.text(
+        rect.center_bottom(),
+        egui::Align2::CENTER_BOTTOM,
+        "θ1",
+        font.clone(),
+        Color32::GRAY,
+    );
+    painter.text(
+        rect.left_center(),
+        egui::Align2::LEFT_CENTER,
+        "θ3",
+        font,
+        Color32::GRAY,
+    );
+}
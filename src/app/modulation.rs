@@ -0,0 +1,123 @@
+use std::f64::consts::TAU;
+
+use super::{FractalPendulumApp, FractalPendulumAppSetting, Ode, dual::Dual};
+
+// 可以调制的参数，顺序与各数组一致
+pub(super) const PARAMETER_NAMES: [&str; 7] = ["l1", "l2", "l3", "m1", "m2", "m3", "g"];
+
+// 参数按正弦规律随时间变化：p(t) = p0 + A·sin(2πf(t0 + t) + φ)，start即积分起点的模拟时间t0
+#[derive(Clone, Copy)]
+pub(super) struct Modulation {
+    amplitude: [f64; 7],
+    frequency: [f64; 7],
+    phase: [f64; 7],
+    start: f64,
+}
+
+impl Modulation {
+    pub(super) fn new(setting: &FractalPendulumAppSetting) -> Option<Self> {
+        (setting.modulated && setting.modulation_amplitude.iter().any(|&a| a != 0.0)).then_some(
+            Self {
+                amplitude: setting.modulation_amplitude,
+                frequency: setting.modulation_frequency,
+                phase: setting.modulation_phase,
+                start: setting.time,
+            },
+        )
+    }
+
+    // 第i个参数在t时刻的偏移量
+    fn offset(&self, i: usize, t: Dual) -> Dual {
+        let omega = TAU * self.frequency[i];
+        ((t + self.start) * omega + self.phase[i]).sin() * self.amplitude[i]
+    }
+
+    // 偏移量的变化率，单独写出来是为了在对时间求导时还能再求一次导
+    fn offset_rate(&self, i: usize, t: Dual) -> Dual {
+        let omega = TAU * self.frequency[i];
+        ((t + self.start) * omega + self.phase[i]).cos() * (self.amplitude[i] * omega)
+    }
+}
+
+impl Ode {
+    // 第k根臂的静止长度
+    pub(super) fn length(&self, k: usize, t: Dual) -> Dual {
+        let offset = self.modulation.map_or(Dual::default(), |m| m.offset(k, t));
+        offset + self.l[k]
+    }
+
+    // 第k根臂静止长度的变化率
+    pub(super) fn length_rate(&self, k: usize, t: Dual) -> Dual {
+        self.modulation
+            .map_or(Dual::default(), |m| m.offset_rate(k, t))
+    }
+
+    // 第k个小球的质量
+    pub(super) fn mass(&self, k: usize, t: Dual) -> Dual {
+        let offset = self
+            .modulation
+            .map_or(Dual::default(), |m| m.offset(3 + k, t));
+        offset + self.m[k]
+    }
+
+    // 重力加速度
+    pub(super) fn gravity_strength(&self, t: Dual) -> Dual {
+        let offset = self.modulation.map_or(Dual::default(), |m| m.offset(6, t));
+        offset + self.g
+    }
+}
+
+impl FractalPendulumAppSetting {
+    // 当前时刻三根臂的静止长度，画图时分形的比例随之变化
+    pub(super) fn modulated_l(&self) -> [f64; 3] {
+        let modulation = Modulation::new(self);
+        std::array::from_fn(|k| {
+            self.l[k] + modulation.map_or(0.0, |m| m.offset(k, Dual::default()).v)
+        })
+    }
+}
+
+impl FractalPendulumApp {
+    pub(super) fn modulation_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.setting.modulated, "启用")
+            .on_hover_text(
+                "参数按正弦规律随时间变化，可以演示参数共振，质量减到零以下时求解会出错",
+            );
+        if !self.setting.modulated {
+            return;
+        }
+
+        egui::Grid::new("调制网格")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("");
+                ui.label("振幅");
+                ui.label("频率");
+                ui.label("相位");
+                ui.end_row();
+
+                for (i, name) in PARAMETER_NAMES.iter().enumerate() {
+                    ui.label(*name);
+                    ui.add(
+                        egui::DragValue::new(&mut self.setting.modulation_amplitude[i]).speed(0.01),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut self.setting.modulation_frequency[i])
+                            .speed(0.01)
+                            .range(0.0..=100.0),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut self.setting.modulation_phase[i])
+                            .speed(0.01)
+                            .range(-std::f64::consts::PI..=std::f64::consts::PI),
+                    );
+                    ui.end_row();
+                }
+            });
+
+        if ui.button("复位").clicked() {
+            self.setting.modulation_amplitude = [0.0; 7];
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use std::time::Duration;
+
+use chrono::Local;
+use nalgebra::{DMatrix, DVector};
+use num_complex::Complex64;
+use ode_solvers::System as _;
+
+use super::{FractalPendulumApp, Ode, State, complex_eigenvalues, wrap_angle};
+
+const MAX_ITERATIONS: usize = 30;
+const TOLERANCE: f64 = 1e-9;
+
+pub(super) struct PeriodicOrbitData {
+    period_guess: f64,
+    result: Option<PeriodicOrbit>,
+    job: Option<Job>,
+    // 正在搜索时已经完成的迭代次数
+    iterations: usize,
+}
+
+impl Default for PeriodicOrbitData {
+    fn default() -> Self {
+        Self {
+            period_guess: 1.0,
+            result: None,
+            job: None,
+            iterations: 0,
+        }
+    }
+}
+
+struct PeriodicOrbit {
+    y: State,
+    period: f64,
+    converged: bool,
+    iterations: usize,
+    residual: f64,
+    // 单值矩阵的特征值，不显含时间的保守系统中总有两个为1
+    multipliers: Vec<Complex64>,
+}
+
+fn total_energy(ode: &Ode, y: &State) -> f64 {
+    let (t, v) = ode.energy(y);
+    t + v
+}
+
+// 打靶法：未知量为起点x和周期T，方程为x(T) = x，不显含时间时加上相位条件，能量守恒时再加上能量不变，
+// 方程可能比未知量多一个，用最小二乘意义下的牛顿迭代求解。迭代的中间状态单独保存，可以一次只迭代一步。
+// 方程里的时间从当前的模拟时间算起，所以积分总从0开始，显含时间的驱动按当前的相位开始，周期固定为初值
+struct Shooting {
+    ode: Ode,
+    x: State,
+    period: f64,
+    energy: f64,
+    h: f64,
+    iterations: usize,
+}
+
+impl Shooting {
+    fn new(ode: Ode, y: State, period: f64, h: f64) -> Self {
+        let energy = total_energy(&ode, &y);
+        Self {
+            ode,
+            x: y,
+            period,
+            energy,
+            h,
+            iterations: 0,
+        }
+    }
+
+    // 做一次牛顿迭代，收敛或次数用完时给出结果，数值计算出错时返回None
+    fn iterate(&mut self) -> Option<Update> {
+        let ode = &self.ode;
+        let h = self.h;
+        let n = self.x.len();
+        let x = &mut self.x;
+        let period = &mut self.period;
+
+        let end = ode.flow(x.clone(), 0.0, *period, h)?;
+        let residual = ode.state_difference(&end, x);
+        // 有阻力或控制力时能量本来就不守恒，再要求能量不变方程就自相矛盾了
+        let conservative = ode.conserves_energy();
+        let energy_residual = if conservative {
+            total_energy(ode, x) - self.energy
+        } else {
+            0.0
+        };
+
+        // 前向差分求单值矩阵
+        let mut monodromy = DMatrix::zeros(n, n);
+        for j in 0..n {
+            let d = 1e-7 * x[j].abs().max(1.0);
+            let mut x_perturbed = x.clone();
+            x_perturbed[j] += d;
+            let end_perturbed = ode.flow(x_perturbed, 0.0, *period, h)?;
+            monodromy.set_column(j, &(ode.state_difference(&end_perturbed, &end) / d));
+        }
+
+        let norm = residual.norm() + energy_residual.abs();
+        if norm < TOLERANCE || self.iterations >= MAX_ITERATIONS {
+            let mut y = x.clone();
+            for &i in ode.angle_indices() {
+                y[i] = wrap_angle(y[i]);
+            }
+            return Some(Update::Finished(Some(PeriodicOrbit {
+                y,
+                period: *period,
+                converged: norm < TOLERANCE,
+                iterations: self.iterations,
+                residual: norm,
+                multipliers: complex_eigenvalues(&monodromy).unwrap_or_default(),
+            })));
+        }
+
+        // 未知量为x和T，前n行为闭合条件，之后依次为相位条件和能量条件。
+        // 显含时间时轨道不能沿时间平移，周期固定为驱动周期的整数倍，不再作为未知量，也没有相位条件
+        let autonomous = ode.is_autonomous();
+        let mut rows = vec![];
+        let mut f_end = State::zeros(n);
+        if autonomous {
+            let mut f_start = State::zeros(n);
+            ode.system(0.0, x, &mut f_start);
+            ode.system(*period, &end, &mut f_end);
+            rows.push((f_start, 0.0));
+        }
+        if conservative {
+            let mut energy_gradient = State::zeros(n);
+            for j in 0..n {
+                let d = 1e-6 * x[j].abs().max(1.0);
+                let mut x_plus = x.clone();
+                let mut x_minus = x.clone();
+                x_plus[j] += d;
+                x_minus[j] -= d;
+                energy_gradient[j] =
+                    (total_energy(ode, &x_plus) - total_energy(ode, &x_minus)) / (2.0 * d);
+            }
+            rows.push((energy_gradient, -energy_residual));
+        }
+
+        let mut a = DMatrix::zeros(n + rows.len(), n + usize::from(autonomous));
+        a.view_mut((0, 0), (n, n))
+            .copy_from(&(monodromy - DMatrix::identity(n, n)));
+        if autonomous {
+            a.view_mut((0, n), (n, 1)).copy_from(&f_end);
+        }
+        let mut b = DVector::zeros(n + rows.len());
+        b.rows_mut(0, n).copy_from(&-residual);
+        for (i, (gradient, value)) in rows.into_iter().enumerate() {
+            a.view_mut((n + i, 0), (1, n))
+                .copy_from(&gradient.transpose());
+            b[n + i] = value;
+        }
+
+        let mut step = a.svd(true, true).solve(&b, 1e-12).ok()?;
+        // 步子太大时缩短，避免跳到别的轨道上
+        let step_norm = step.norm();
+        if step_norm > 0.5 {
+            step *= 0.5 / step_norm;
+        }
+
+        *x += step.rows(0, n);
+        if autonomous {
+            *period += step[n];
+            if *period <= 0.0 {
+                return None;
+            }
+        }
+        self.iterations += 1;
+        Some(Update::Iteration(self.iterations))
+    }
+}
+
+// 搜索的进展：完成了第几次迭代，或者最终结果，出错时结果为None
+enum Update {
+    Iteration(usize),
+    Finished(Option<PeriodicOrbit>),
+}
+
+// 正在进行的搜索。原生平台在后台线程里算，网页上每帧迭代一次
+#[cfg(not(target_arch = "wasm32"))]
+struct Job {
+    receiver: std::sync::mpsc::Receiver<Update>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Job {
+    fn start(mut shooting: Shooting) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        // 接收端被丢弃后发送失败，线程随之退出
+        std::thread::spawn(move || {
+            loop {
+                let update = shooting.iterate().unwrap_or(Update::Finished(None));
+                let finished = matches!(update, Update::Finished(_));
+                if sender.send(update).is_err() || finished {
+                    break;
+                }
+            }
+        });
+        Self { receiver }
+    }
+
+    fn poll(&self) -> Vec<Update> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+struct Job {
+    shooting: Shooting,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Job {
+    fn start(shooting: Shooting) -> Self {
+        Self { shooting }
+    }
+
+    fn poll(&mut self) -> Vec<Update> {
+        vec![self.shooting.iterate().unwrap_or(Update::Finished(None))]
+    }
+}
+
+impl FractalPendulumApp {
+    // 每帧都要调用，收集后台搜索的结果
+    pub(super) fn poll_periodic_orbit(&mut self, ctx: &egui::Context) {
+        let data = &mut self.data.periodic_orbit;
+        let Some(job) = &mut data.job else {
+            return;
+        };
+        for update in job.poll() {
+            match update {
+                Update::Iteration(iterations) => data.iterations = iterations,
+                Update::Finished(result) => {
+                    if result.is_none() {
+                        self.data
+                            .toasts
+                            .warning("数值计算出错，搜索失败")
+                            .duration(Some(Duration::from_secs(5)))
+                            .show_progress_bar(true);
+                    }
+                    data.result = result;
+                    data.job = None;
+                    return;
+                }
+            }
+        }
+        ctx.request_repaint();
+    }
+
+    pub(super) fn periodic_orbit_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(&mut self.data.periodic_orbit.period_guess)
+                    .speed(0.01)
+                    .range(0.01..=100.0)
+                    .prefix("周期初值："),
+            );
+
+            if ui
+                .button("搜索")
+                .on_hover_text("从当前状态出发寻找附近的周期轨道，能量守恒时保持能量不变。显含时间时周期固定为初值，应取驱动周期的整数倍，驱动从当前的相位开始")
+                .clicked()
+            {
+                self.data.periodic_orbit.job = Some(Job::start(Shooting::new(
+                    self.controlled_ode(&self.setting),
+                    self.setting.state(),
+                    self.data.periodic_orbit.period_guess,
+                    self.setting.h,
+                )));
+                self.data.periodic_orbit.iterations = 0;
+            }
+
+            if self.data.periodic_orbit.job.is_some() {
+                ui.spinner();
+                ui.label(format!(
+                    "第{}次迭代",
+                    self.data.periodic_orbit.iterations + 1
+                ));
+                if ui.button("停止").clicked() {
+                    self.data.periodic_orbit.job = None;
+                }
+            }
+        });
+
+        let Some(orbit) = &self.data.periodic_orbit.result else {
+            return;
+        };
+
+        egui::Grid::new("周期轨道网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("结果");
+                ui.label(if orbit.converged {
+                    format!("收敛（{}次迭代）", orbit.iterations)
+                } else {
+                    format!("未收敛（{}次迭代）", orbit.iterations)
+                });
+                ui.end_row();
+
+                ui.label("周期");
+                ui.label(orbit.period.to_string());
+                ui.end_row();
+
+                ui.label("残差");
+                ui.label(format!("{:.3e}", orbit.residual));
+                ui.end_row();
+
+                ui.label("Floquet乘子")
+                    .on_hover_text("模长均为1时轨道线性稳定，有模长大于1的乘子时不稳定");
+                let max_modulus = orbit
+                    .multipliers
+                    .iter()
+                    .map(|m| m.norm())
+                    .fold(0.0, f64::max);
+                ui.label(format!("最大模长{max_modulus:.4}")).on_hover_text(
+                    orbit
+                        .multipliers
+                        .iter()
+                        .map(|m| format!("{:.4}{:+.4}i", m.re, m.im))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                );
+                ui.end_row();
+            });
+
+        let y = orbit.y.clone();
+        ui.horizontal(|ui| {
+            if ui.button("载入").clicked() {
+                self.setting.set_state(&y);
+                self.data
+                    .toasts
+                    .info("已载入周期轨道")
+                    .duration(Some(Duration::from_secs(5)))
+                    .show_progress_bar(true);
+            }
+
+            if ui.button("收藏").clicked() {
+                let mut setting = self.setting.clone();
+                setting.set_state(&y);
+                self.favorites.insert(Local::now(), setting);
+                self.data
+                    .toasts
+                    .info("已收藏周期轨道")
+                    .duration(Some(Duration::from_secs(5)))
+                    .show_progress_bar(true);
+            }
+        });
+    }
+}
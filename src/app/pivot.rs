@@ -0,0 +1,228 @@
+use std::f64::consts::TAU;
+
+use egui::{Pos2, Sense};
+
+use super::{
+    FractalPendulumApp, FractalPendulumAppSetting,
+    dual::{Dual, DualVec2},
+};
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy)]
+pub(super) enum PivotMode {
+    Fixed,
+    Drag,
+    Script,
+}
+
+// 支点在一次积分内的运动，时间从积分起点算起
+#[derive(Clone, Copy)]
+pub(super) enum Pivot {
+    // 位置、速度和加速度，一次积分内加速度不变
+    Uniform {
+        p: [f64; 2],
+        v: [f64; 2],
+        a: [f64; 2],
+    },
+    // 两个方向各自做简谐运动，start为积分起点的模拟时间
+    Script {
+        amplitude: [f64; 2],
+        frequency: [f64; 2],
+        phase: [f64; 2],
+        start: f64,
+    },
+}
+
+impl Pivot {
+    // 拖动时由界面另外给出，这里只处理脚本
+    pub(super) fn new(setting: &FractalPendulumAppSetting) -> Option<Self> {
+        (setting.pivot_mode == PivotMode::Script).then_some(Self::Script {
+            amplitude: setting.pivot_amplitude,
+            frequency: setting.pivot_frequency,
+            phase: setting.pivot_phase,
+            start: setting.time,
+        })
+    }
+
+    // t时刻的位置
+    fn position(&self, t: f64) -> [f64; 2] {
+        match *self {
+            Self::Uniform { p, v, a } => {
+                std::array::from_fn(|i| p[i] + v[i] * t + 0.5 * a[i] * t * t)
+            }
+            Self::Script {
+                amplitude,
+                frequency,
+                phase,
+                start,
+            } => std::array::from_fn(|i| {
+                amplitude[i] * (TAU * frequency[i] * (start + t) + phase[i]).sin()
+            }),
+        }
+    }
+
+    // t时刻的加速度
+    pub(super) fn acceleration(&self, t: Dual) -> DualVec2 {
+        match *self {
+            Self::Uniform { a, .. } => DualVec2::new(Dual::constant(a[0]), Dual::constant(a[1])),
+            Self::Script {
+                amplitude,
+                frequency,
+                phase,
+                start,
+            } => {
+                let axis = |i: usize| {
+                    let omega = TAU * frequency[i];
+                    ((t + start) * omega + phase[i]).sin() * (-amplitude[i] * omega * omega)
+                };
+                DualVec2::new(axis(0), axis(1))
+            }
+        }
+    }
+}
+
+// 拖动模式下支点的状态，支点以临界阻尼的方式追随鼠标
+pub(super) struct PivotData {
+    p: [f64; 2],
+    v: [f64; 2],
+    target: Option<[f64; 2]>,
+}
+
+impl Default for PivotData {
+    fn default() -> Self {
+        Self {
+            p: [0.0; 2],
+            v: [0.0; 2],
+            target: None,
+        }
+    }
+}
+
+impl FractalPendulumApp {
+    // 拖动模式下这一帧内支点的运动，松开鼠标后减速停下
+    pub(super) fn drag_pivot(&self) -> Option<Pivot> {
+        if self.setting.pivot_mode != PivotMode::Drag {
+            return None;
+        }
+        let data = &self.data.pivot;
+        let omega = self.setting.pivot_response;
+        let a = std::array::from_fn(|i| {
+            let pull = data.target.map_or(0.0, |target| target[i] - data.p[i]);
+            omega * omega * pull - 2.0 * omega * data.v[i]
+        });
+        Some(Pivot::Uniform {
+            p: data.p,
+            v: data.v,
+            a,
+        })
+    }
+
+    // 积分完一帧之后推进支点的状态
+    pub(super) fn advance_pivot(&mut self, pivot: Option<Pivot>) {
+        if let Some(Pivot::Uniform { p, v, a }) = pivot {
+            let dt = self.setting.delta_t;
+            let data = &mut self.data.pivot;
+            for i in 0..2 {
+                data.p[i] = p[i] + v[i] * dt + 0.5 * a[i] * dt * dt;
+                data.v[i] = v[i] + a[i] * dt;
+            }
+        }
+    }
+
+    // 支点当前相对偏置的位移，用于画图
+    pub(super) fn pivot_position(&self) -> [f64; 2] {
+        match self.setting.pivot_mode {
+            PivotMode::Fixed => [0.0; 2],
+            PivotMode::Drag => self.data.pivot.p,
+            PivotMode::Script => {
+                Pivot::new(&self.setting).map_or([0.0; 2], |pivot| pivot.position(0.0))
+            }
+        }
+    }
+
+    // 在画布上按住拖动支点
+    pub(super) fn pivot_interact(
+        &mut self,
+        ui: &egui::Ui,
+        rect: egui::Rect,
+        from_screen: &egui::emath::RectTransform,
+    ) {
+        // 球面摆时拖动鼠标用来旋转视角
+        if self.setting.pivot_mode != PivotMode::Drag || self.setting.spherical {
+            return;
+        }
+        let response = ui.interact(rect, ui.id().with("拖动支点"), Sense::drag());
+        self.data.pivot.target = if response.dragged() {
+            response.interact_pointer_pos().map(|pos| {
+                let Pos2 { x, y } = from_screen * pos;
+                [
+                    f64::from(x) - self.setting.x_offset,
+                    f64::from(y) - self.setting.y_offset,
+                ]
+            })
+        } else {
+            None
+        };
+    }
+
+    pub(super) fn pivot_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.setting.pivot_mode, PivotMode::Fixed, "固定");
+            ui.selectable_value(&mut self.setting.pivot_mode, PivotMode::Drag, "拖动")
+                .on_hover_text("在画布上按住鼠标拖动支点");
+            ui.selectable_value(&mut self.setting.pivot_mode, PivotMode::Script, "简谐");
+        });
+
+        match self.setting.pivot_mode {
+            PivotMode::Fixed => {}
+            PivotMode::Drag => {
+                ui.add(
+                    egui::Slider::new(&mut self.setting.pivot_response, 1.0..=1000.0)
+                        .logarithmic(true)
+                        .clamping(egui::SliderClamping::Never)
+                        .text("跟随速度"),
+                )
+                .on_hover_text("支点追随鼠标的角频率，按模拟时间计，Δt较小时需要调大");
+                if ui.button("归位").clicked() {
+                    self.data.pivot = PivotData::default();
+                }
+            }
+            PivotMode::Script => {
+                egui::Grid::new("支点运动网格")
+                    .num_columns(3)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("");
+                        ui.label("x");
+                        ui.label("y");
+                        ui.end_row();
+
+                        ui.label("振幅");
+                        for amplitude in &mut self.setting.pivot_amplitude {
+                            ui.add(egui::DragValue::new(amplitude).speed(0.01));
+                        }
+                        ui.end_row();
+
+                        ui.label("频率");
+                        for frequency in &mut self.setting.pivot_frequency {
+                            ui.add(
+                                egui::DragValue::new(frequency)
+                                    .speed(0.01)
+                                    .range(0.0..=100.0),
+                            );
+                        }
+                        ui.end_row();
+
+                        ui.label("相位");
+                        for phase in &mut self.setting.pivot_phase {
+                            ui.add(
+                                egui::DragValue::new(phase)
+                                    .speed(0.01)
+                                    .range(-std::f64::consts::PI..=std::f64::consts::PI),
+                            );
+                        }
+                        ui.end_row();
+                    });
+            }
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use egui::{Color32, Pos2, Rect, Vec2};
+use num_complex::Complex64;
+
+use super::{CullStats, FractalPendulumApp, tree};
+
+// 每帧最多处理的节点数
+const BUDGET: usize = 1 << 17;
+
+// 渐进渲染依赖的全部输入，任何一项变了都要从头画
+#[derive(PartialEq)]
+struct Key {
+    transforms: [Complex64; 2],
+    root: tree::Node,
+    view: tree::View,
+    hue: [f32; 2],
+    style: [f32; 3],
+    decay: [f32; 3],
+    threshold: f32,
+    target: usize,
+    rect: Rect,
+    pixels_per_point: f32,
+    depth: usize,
+}
+
+// 暂停时把比递归深度更深的层逐帧画到纹理上，垫在分形下面。
+// 每层单独深度优先遍历一遍，只画这一层的线段，画完垫到已经画好的层下面，所以深的层总在底下
+#[derive(Default)]
+pub(super) struct ProgressiveData {
+    key: Option<Key>,
+    // 正在画的层和遍历用的栈
+    level: usize,
+    stack: Vec<(tree::Node, usize)>,
+    size: [usize; 2],
+    // 预乘透明度的颜色，layer是正在画的这一层，canvas是已经画好的
+    layer: Vec<[f32; 4]>,
+    canvas: Vec<[f32; 4]>,
+    texture: Option<egui::TextureHandle>,
+}
+
+impl ProgressiveData {
+    // 正在画的层以及整体进度，没有在画时返回None
+    pub(super) fn progress(&self, depth: usize, target: usize) -> Option<(usize, f32)> {
+        (self.key.is_some() && self.level <= target && self.level > depth).then(|| {
+            (
+                self.level,
+                (self.level - depth - 1) as f32 / (target - depth) as f32,
+            )
+        })
+    }
+
+    fn clear(&mut self, key: Key, size: [usize; 2], level: usize, root: tree::Node) {
+        self.key = Some(key);
+        self.level = level;
+        self.stack.clear();
+        self.stack.push((root, 0));
+        self.size = size;
+        self.layer = vec![[0.0; 4]; size[0] * size[1]];
+        self.canvas = vec![[0.0; 4]; size[0] * size[1]];
+        self.texture = None;
+    }
+
+    // 最多处理budget个节点，这一层画完时垫到canvas下面，开始下一层
+    fn step(
+        &mut self,
+        grower: &tree::Grower,
+        root: tree::Node,
+        scale: f32,
+        origin: Pos2,
+        mut budget: usize,
+    ) {
+        let reach = grower.reach();
+        let mut cull = CullStats::default();
+        while budget > 0 && self.level <= grower.depth {
+            let Some((node, level)) = self.stack.pop() else {
+                for (canvas, layer) in self.canvas.iter_mut().zip(&mut self.layer) {
+                    let under = 1.0 - canvas[3];
+                    for (c, l) in canvas.iter_mut().zip(*layer) {
+                        *c += l * under;
+                    }
+                    *layer = [0.0; 4];
+                }
+                self.level += 1;
+                self.stack.push((root, 0));
+                continue;
+            };
+            budget -= 1;
+
+            if level == self.level {
+                if let Some(segment) = grower.paint(&node, level) {
+                    rasterize(&mut self.layer, self.size, &segment, scale, origin);
+                }
+            } else if grower.expand(&node, &reach, grower.depth - level, &mut cull) {
+                // 倒着压栈，出栈时按原来的顺序
+                for (branch, &transform) in grower.transforms.iter().enumerate().rev() {
+                    self.stack.push((node.apply(transform, branch), level + 1));
+                }
+            }
+        }
+        if self.level > grower.depth {
+            self.stack.clear();
+        }
+    }
+
+    // 正在画的层垫在已经画好的下面一起显示
+    fn image(&self) -> egui::ColorImage {
+        let pixels = self
+            .canvas
+            .iter()
+            .zip(&self.layer)
+            .map(|(canvas, layer)| {
+                let under = 1.0 - canvas[3];
+                let [r, g, b, a] =
+                    std::array::from_fn(|i| ((canvas[i] + layer[i] * under) * 255.0).round() as u8);
+                Color32::from_rgba_premultiplied(r, g, b, a)
+            })
+            .collect();
+        egui::ColorImage::new(self.size, pixels)
+    }
+}
+
+// 按到线段的距离算覆盖率，两端自然是圆头。不到一个像素宽的线按宽度调淡
+fn rasterize(
+    layer: &mut [[f32; 4]],
+    [width, height]: [usize; 2],
+    segment: &tree::Segment,
+    scale: f32,
+    origin: Pos2,
+) {
+    let [a, b] = segment.line.map(|p| ((p - origin) * scale).to_pos2());
+    let thickness = segment.width * scale;
+    let half = 0.5 * thickness.max(1.0);
+    let fade = thickness.min(1.0);
+    let color = segment.color.to_normalized_gamma_f32();
+
+    let min = a.min(b) - Vec2::splat(half + 1.0);
+    let max = a.max(b) + Vec2::splat(half + 1.0);
+    let x0 = min.x.max(0.0) as usize;
+    let y0 = min.y.max(0.0) as usize;
+    let x1 = (max.x.max(0.0) as usize).min(width);
+    let y1 = (max.y.max(0.0) as usize).min(height);
+
+    let ab = b - a;
+    let length_sq = ab.length_sq();
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let t = if length_sq > 0.0 {
+                ((p - a).dot(ab) / length_sq).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let distance = (p - (a + ab * t)).length();
+            let coverage = (half + 0.5 - distance).clamp(0.0, 1.0) * fade;
+            if coverage > 0.0 {
+                let pixel = &mut layer[y * width + x];
+                let keep = 1.0 - color[3] * coverage;
+                for (p, c) in pixel.iter_mut().zip(color) {
+                    *p = c * coverage + *p * keep;
+                }
+            }
+        }
+    }
+}
+
+impl FractalPendulumApp {
+    // 暂停且开启渐进渲染时，逐帧往纹理上加更深的层，画在分形下面
+    pub(super) fn paint_progressive(
+        &mut self,
+        painter: &egui::Painter,
+        to_screen: &egui::emath::RectTransform,
+        hue: [f32; 2],
+    ) {
+        let target = self.setting.progressive_depth;
+        if !self.data.paused
+            || !self.setting.progressive
+            || self.setting.spherical
+            || target <= self.render_depth()
+        {
+            if self.data.progressive.key.is_some() {
+                self.data.progressive = ProgressiveData::default();
+            }
+            return;
+        }
+
+        let rect = painter.clip_rect();
+        let pixels_per_point = painter.ctx().pixels_per_point();
+        let (grower, root) = self.grower(&self.setting.state(), hue, target, to_screen);
+        let key = Key {
+            transforms: grower.transforms,
+            root,
+            view: grower.view,
+            hue: grower.hue,
+            style: grower.style,
+            decay: grower.decay,
+            threshold: grower.threshold,
+            target,
+            rect,
+            pixels_per_point,
+            depth: self.render_depth(),
+        };
+
+        let data = &mut self.data.progressive;
+        if data.key.as_ref() != Some(&key) {
+            let size = (rect.size() * pixels_per_point).round();
+            let level = key.depth + 1;
+            data.clear(key, [size.x as usize, size.y as usize], level, root);
+        }
+
+        if !data.stack.is_empty() {
+            data.step(&grower, root, pixels_per_point, rect.min, BUDGET);
+            let image = data.image();
+            match &mut data.texture {
+                Some(texture) => texture.set(image, egui::TextureOptions::LINEAR),
+                None => {
+                    data.texture = Some(painter.ctx().load_texture(
+                        "渐进渲染",
+                        image,
+                        egui::TextureOptions::LINEAR,
+                    ));
+                }
+            }
+            painter.ctx().request_repaint();
+        }
+
+        if let Some(texture) = &data.texture {
+            painter.image(
+                texture.id(),
+                rect,
+                Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+                Color32::WHITE,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 64;
+
+    // 世界坐标[-2, 2]²映到64×64的画面上，已经画到第3层，往下画到第14层
+    fn setup() -> (ProgressiveData, tree::Grower, tree::Node) {
+        let rect = Rect::from_min_size(Pos2::ZERO, Vec2::splat(SIZE as f32));
+        let to_screen = egui::emath::RectTransform::from_to(
+            Rect::from_center_size(Pos2::ZERO, Vec2::splat(4.0)),
+            rect,
+        );
+        let grower = tree::Grower {
+            transforms: [
+                Complex64::from_polar(0.6, 0.5),
+                Complex64::from_polar(0.6, -0.7),
+            ],
+            depth: 14,
+            view: tree::View::new(&to_screen),
+            hue: [0.0, 1.0],
+            style: [1.0; 3],
+            decay: [0.9; 3],
+            threshold: 0.0,
+        };
+        let root = tree::Node::root(Complex64::new(0.0, 1.0), Complex64::new(0.0, -1.0));
+        let key = Key {
+            transforms: grower.transforms,
+            root,
+            view: grower.view,
+            hue: grower.hue,
+            style: grower.style,
+            decay: grower.decay,
+            threshold: grower.threshold,
+            target: grower.depth,
+            rect,
+            pixels_per_point: 1.0,
+            depth: 3,
+        };
+        let mut data = ProgressiveData::default();
+        data.clear(key, [SIZE; 2], 4, root);
+        (data, grower, root)
+    }
+
+    #[test]
+    fn resuming_matches_an_uninterrupted_run() {
+        let (mut whole, grower, root) = setup();
+        whole.step(&grower, root, 1.0, Pos2::ZERO, usize::MAX);
+        assert!(whole.stack.is_empty());
+        assert!(
+            whole.canvas.iter().any(|pixel| pixel[3] > 0.0),
+            "什么都没画"
+        );
+
+        // 预算很小时分很多帧画完，进度只增不减，最后的结果一样
+        let (mut pieces, _, _) = setup();
+        let mut frames = 0;
+        let mut last = 0.0;
+        while !pieces.stack.is_empty() {
+            pieces.step(&grower, root, 1.0, Pos2::ZERO, 1000);
+            frames += 1;
+            if let Some((_, fraction)) = pieces.progress(3, grower.depth) {
+                assert!(fraction >= last, "进度从{last}退到了{fraction}");
+                last = fraction;
+            }
+        }
+        assert!(frames > 10, "只用了{frames}帧");
+        assert!(
+            whole.canvas == pieces.canvas,
+            "分帧画出来的和一次画完的不一样"
+        );
+    }
+
+    #[test]
+    fn budget_limits_one_frame() {
+        let (mut data, grower, root) = setup();
+        data.step(&grower, root, 1.0, Pos2::ZERO, 1);
+        // 只处理了根节点，它的两个子节点还在栈里
+        assert_eq!(data.level, 4);
+        assert_eq!(data.stack.len(), 2);
+        assert!(data.layer.iter().all(|pixel| pixel[3] == 0.0));
+    }
+
+    #[test]
+    fn rasterize_covers_the_segment() {
+        let mut layer = vec![[0.0; 4]; 8 * 8];
+        let segment = |y, width| tree::Segment {
+            line: [Pos2::new(1.0, y), Pos2::new(7.0, y)],
+            width,
+            color: Color32::WHITE,
+        };
+        rasterize(&mut layer, [8, 8], &segment(4.0, 2.0), 1.0, Pos2::ZERO);
+        // 线上的像素全部盖住，离开线宽的像素不受影响
+        assert_eq!(layer[4 * 8 + 4], [1.0; 4]);
+        assert_eq!(layer[3 * 8 + 4], [1.0; 4]);
+        assert_eq!(layer[8 + 4], [0.0; 4]);
+        assert_eq!(layer[6 * 8 + 4], [0.0; 4]);
+
+        // 不到一个像素宽的线按宽度调淡
+        let mut layer = vec![[0.0; 4]; 8 * 8];
+        rasterize(&mut layer, [8, 8], &segment(4.5, 0.5), 1.0, Pos2::ZERO);
+        assert!((layer[4 * 8 + 4][3] - 0.5).abs() < 1e-6);
+    }
+}
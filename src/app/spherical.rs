@@ -0,0 +1,439 @@
+use std::f64::consts::PI;
+
+use egui::{Pos2, Sense, Shape};
+use nalgebra::{DMatrix, DVector, UnitQuaternion, Vector3};
+
+use super::{
+    FractalPendulumApp, FractalPendulumAppSetting, Ode, State,
+    constraint::Constraints,
+    dual::{Dual, DualVec3},
+    hsl_to_rgb, lerp,
+};
+
+// 三维模式不剪枝，每层的线段都要生成、投影再排序，递归深度最多到这里
+pub(super) const MAX_DEPTH: usize = 16;
+
+// 球面摆：每根臂在原来的角度θ之外多一个离开画面的角度φ，臂的方向为Rz(θ)Rx(φ)乘上竖直向下的单位向量，
+// 第二、三根臂的转动接在第一根臂后面。φ全为零时退化为原来的平面三摆，奇点在臂垂直画面时，离静止位置很远。
+// 广义坐标依次为θ1、θ2、θ3、φ1、φ2、φ3，只用到质量、长度和重力，其余变体都不生效
+impl Ode {
+    pub(super) fn spherical(setting: &FractalPendulumAppSetting) -> Self {
+        Self {
+            g: setting.g,
+            l: setting.l,
+            m: setting.m,
+            stiffness: None,
+            compound: None,
+            pivot: None,
+            cart: None,
+            magnets: None,
+            modulation: None,
+            frame: None,
+            spherical: true,
+            symplectic: setting.hamiltonian,
+            constraints: Constraints::default(),
+        }
+    }
+
+    // 三根臂的方向
+    fn sphere_directions(x: &[Dual]) -> [DualVec3; 3] {
+        let first = |v: DualVec3| v.rotate_x(x[3]).rotate_z(x[0]);
+        let own = |k: usize| DualVec3::down().rotate_x(x[3 + k]).rotate_z(x[k]);
+        [own(0), first(own(1)), first(own(2))]
+    }
+
+    // 三个小球相对支点的位置
+    fn sphere_positions(&self, x: &[Dual]) -> [DualVec3; 3] {
+        let [u1, u2, u3] = Self::sphere_directions(x);
+        let p1 = u1 * Dual::constant(self.l[0]);
+        [
+            p1,
+            p1 + u2 * Dual::constant(self.l[1]),
+            p1 + u3 * Dual::constant(self.l[2]),
+        ]
+    }
+
+    // 三个小球的质量和速度，动能为Σ½m|c|²。沿用平面时的约定，每根臂只按自己的角速度转动
+    fn sphere_terms(&self, x: &[Dual], v: &[Dual]) -> [(Dual, DualVec3); 3] {
+        let first = |w: DualVec3| w.rotate_x(x[3]).rotate_z(x[0]);
+        let own = |k: usize| {
+            let d = DualVec3::down();
+            d.rotate_x(x[3 + k]).rotate_z_rate(x[k]) * v[k]
+                + d.rotate_x_rate(x[3 + k]).rotate_z(x[k]) * v[3 + k]
+        };
+        let c1 = own(0) * Dual::constant(self.l[0]);
+        let c2 = c1 + first(own(1)) * Dual::constant(self.l[1]);
+        let c3 = c1 + first(own(2)) * Dual::constant(self.l[2]);
+        let m = self.m.map(Dual::constant);
+        [(m[0], c1), (m[1], c2), (m[2], c3)]
+    }
+
+    fn sphere_lagrangian(&self, x: &[Dual], v: &[Dual]) -> Dual {
+        let p = self.sphere_positions(x);
+        let kinetic = self
+            .sphere_terms(x, v)
+            .into_iter()
+            .fold(Dual::default(), |sum, (m, c)| {
+                sum + c.norm_squared() * m * 0.5
+            });
+        let potential = p
+            .into_iter()
+            .zip(self.m)
+            .fold(Dual::default(), |sum, (p, m)| sum - p.y * (m * self.g));
+        kinetic - potential
+    }
+
+    // 速度对广义速度的导数矩阵的各列，这里速度不含与广义速度无关的部分
+    fn sphere_columns(&self, x: &[Dual]) -> Vec<[DualVec3; 3]> {
+        (0..6)
+            .map(|j| {
+                let e: Vec<Dual> = (0..6)
+                    .map(|i| Dual::constant(if i == j { 1.0 } else { 0.0 }))
+                    .collect();
+                self.sphere_terms(x, &e).map(|(_, c)| c)
+            })
+            .collect()
+    }
+
+    fn sphere_mass_matrix_from(&self, columns: &[[DualVec3; 3]]) -> DMatrix<f64> {
+        DMatrix::from_fn(6, 6, |i, j| {
+            (0..3)
+                .map(|k| self.m[k] * columns[i][k].dot(columns[j][k]).v)
+                .sum()
+        })
+    }
+
+    pub(super) fn spherical_mass_matrix(&self, y: &State) -> DMatrix<f64> {
+        let x: Vec<Dual> = (0..6).map(|j| Dual::constant(y[2 * j])).collect();
+        self.sphere_mass_matrix_from(&self.sphere_columns(&x))
+    }
+
+    // 与平面时的做法相同，对偶部分取沿运动方向的时间导数
+    pub(super) fn spherical_acceleration(&self, y: &State) -> Option<DVector<f64>> {
+        let x: Vec<f64> = (0..6).map(|j| y[2 * j]).collect();
+        let v: Vec<f64> = (0..6).map(|j| y[2 * j + 1]).collect();
+        let x_moving: Vec<Dual> = x.iter().zip(&v).map(|(&x, &v)| Dual::new(x, v)).collect();
+        let v_constant: Vec<Dual> = v.iter().copied().map(Dual::constant).collect();
+
+        let columns = self.sphere_columns(&x_moving);
+        let m = self.sphere_mass_matrix_from(&columns);
+        let current = self.sphere_terms(&x_moving, &v_constant);
+
+        let rhs = DVector::from_fn(6, |j, _| {
+            // d/dt(∂L/∂vj)在加速度为零时的值
+            let momentum_rate: f64 = current
+                .iter()
+                .enumerate()
+                .map(|(k, (mass, c))| (*mass * columns[j][k].dot(*c)).d)
+                .sum();
+
+            // ∂L/∂xj
+            let x_seeded: Vec<Dual> = x
+                .iter()
+                .enumerate()
+                .map(|(i, &x)| Dual::new(x, if i == j { 1.0 } else { 0.0 }))
+                .collect();
+            self.sphere_lagrangian(&x_seeded, &v_constant).d - momentum_rate
+        });
+
+        Some(m.cholesky()?.solve(&rhs))
+    }
+
+    // ∂L/∂x
+    pub(super) fn spherical_force(&self, y: &State) -> DVector<f64> {
+        let v: Vec<Dual> = (0..6).map(|j| Dual::constant(y[2 * j + 1])).collect();
+        DVector::from_fn(6, |j, _| {
+            let x: Vec<Dual> = (0..6)
+                .map(|i| Dual::new(y[2 * i], if i == j { 1.0 } else { 0.0 }))
+                .collect();
+            self.sphere_lagrangian(&x, &v).d
+        })
+    }
+
+    pub(super) fn spherical_lagrangian(&self, y: &State) -> f64 {
+        let x: Vec<Dual> = (0..6).map(|j| Dual::constant(y[2 * j])).collect();
+        let v: Vec<Dual> = (0..6).map(|j| Dual::constant(y[2 * j + 1])).collect();
+        self.sphere_lagrangian(&x, &v).v
+    }
+
+    pub(super) fn spherical_energy(&self, y: &State) -> (f64, f64) {
+        let x: Vec<Dual> = (0..6).map(|j| Dual::constant(y[2 * j])).collect();
+        let v: Vec<Dual> = (0..6).map(|j| Dual::constant(y[2 * j + 1])).collect();
+        let kinetic = self
+            .sphere_terms(&x, &v)
+            .into_iter()
+            .map(|(m, c)| 0.5 * m.v * c.norm_squared().v)
+            .sum();
+        let potential = self
+            .sphere_positions(&x)
+            .into_iter()
+            .zip(self.m)
+            .map(|(p, m)| -m * self.g * p.y.v)
+            .sum();
+        (kinetic, potential)
+    }
+}
+
+// 空间中的一段线段，颜色要等知道深度范围后再定
+struct Segment {
+    start: Vector3<f32>,
+    end: Vector3<f32>,
+    hue: f32,
+    saturation: f32,
+    luminance: f32,
+    width: f32,
+}
+
+// 先绕z轴转θ再绕x轴转φ，与方程里的臂方向一致
+fn arm_rotation(theta: f64, phi: f64) -> UnitQuaternion<f32> {
+    UnitQuaternion::from_axis_angle(&Vector3::z_axis(), theta as f32)
+        * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), phi as f32)
+}
+
+impl FractalPendulumApp {
+    // 相机的朝向，先绕竖直轴转再俯仰
+    fn camera_rotation(&self) -> UnitQuaternion<f32> {
+        UnitQuaternion::from_axis_angle(&Vector3::x_axis(), self.setting.camera_pitch)
+            * UnitQuaternion::from_axis_angle(&Vector3::y_axis(), self.setting.camera_yaw)
+    }
+
+    // 在画布上拖动鼠标绕支点旋转视角
+    pub(super) fn camera_interact(&mut self, ui: &egui::Ui, rect: egui::Rect) {
+        if !self.setting.spherical {
+            return;
+        }
+        let response = ui.interact(rect, ui.id().with("旋转视角"), Sense::drag());
+        let delta = response.drag_delta();
+        self.setting.camera_yaw -= delta.x * 0.01;
+        self.setting.camera_pitch = (self.setting.camera_pitch - delta.y * 0.01)
+            .clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
+    }
+
+    #[expect(clippy::too_many_lines)]
+    // 三维分形：节点带着朝向四元数迭代，透视投影到画布上，远处的线段画得暗一些，按深度从远到近排好
+    pub(super) fn sphere_shapes(
+        &self,
+        y: &State,
+        h1: f32,
+        h2: f32,
+        to_screen: &egui::emath::RectTransform,
+    ) -> Vec<Shape> {
+        #[derive(Clone, Copy)]
+        struct Node {
+            start: Vector3<f32>,
+            rotation: UnitQuaternion<f32>,
+            length: f32,
+        }
+
+        impl Node {
+            fn end(&self) -> Vector3<f32> {
+                self.start + self.rotation * Vector3::new(0.0, self.length, 0.0)
+            }
+
+            fn apply(&self, (rotation, scale): (UnitQuaternion<f32>, f32)) -> Self {
+                Self {
+                    start: self.end(),
+                    rotation: self.rotation * rotation,
+                    length: self.length * scale,
+                }
+            }
+        }
+
+        let [l1, l2, l3] = self.setting.l.map(|l| l as f32);
+        let transforms = [
+            (arm_rotation(y[2], y[8]), l2 / l1),
+            (arm_rotation(y[4], y[10]), l3 / l1),
+        ];
+        let root = Node {
+            start: Vector3::zeros(),
+            rotation: arm_rotation(y[0], y[6]),
+            length: l1,
+        };
+
+        // 先在空间中生成全部线段
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut nodes = vec![root];
+        let mut width = self.setting.line_width;
+        let mut luminance = self.setting.luminance;
+        let mut saturation = self.setting.saturation;
+        let depth = self.render_depth();
+        for level in 0..=depth {
+            if level > 0 {
+                width *= self.setting.width_decay;
+                luminance *= self.setting.luminance_decay;
+                saturation *= self.setting.saturation_decay;
+            }
+            let count = nodes.len();
+            segments.extend(nodes.iter().enumerate().map(|(i, node)| Segment {
+                start: node.start,
+                end: node.end(),
+                hue: lerp(h1, h2, (i as f32 + 0.5) / count as f32),
+                saturation,
+                luminance,
+                width,
+            }));
+            if level < depth {
+                nodes = nodes
+                    .iter()
+                    .flat_map(|node| transforms.map(|transform| node.apply(transform)))
+                    .collect();
+            }
+        }
+
+        // 转到相机坐标系，z为离支点所在平面的深度
+        let camera = self.camera_rotation();
+        let distance = self.setting.camera_distance;
+        let project = |p: Vector3<f32>| {
+            let p = camera * p;
+            let scale = distance / (distance + p.z);
+            (
+                to_screen
+                    * Pos2::new(
+                        p.x * scale + self.setting.x_offset as f32,
+                        p.y * scale + self.setting.y_offset as f32,
+                    ),
+                p.z,
+                scale,
+            )
+        };
+
+        // 球和线段一起按深度排序
+        let mut items: Vec<(f32, Shape)> = Vec::new();
+        let projected: Vec<_> = segments
+            .iter()
+            .map(|s| (project(s.start), project(s.end)))
+            .collect();
+        let (near, far) = projected
+            .iter()
+            .flat_map(|(a, b)| [a.1, b.1])
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), z| {
+                (lo.min(z), hi.max(z))
+            });
+        let shade = |z: f32| {
+            let t = if far > near {
+                (z - near) / (far - near)
+            } else {
+                0.0
+            };
+            1.0 - self.setting.depth_shading * t
+        };
+
+        let rect = *to_screen.to();
+        for (segment, (a, b)) in segments.iter().zip(&projected) {
+            // 跑到相机后面的不画
+            if a.2 <= 0.0 || b.2 <= 0.0 {
+                continue;
+            }
+            if !rect.intersects(egui::Rect::from_two_pos(a.0, b.0)) {
+                continue;
+            }
+            let z = 0.5 * (a.1 + b.1);
+            let color = hsl_to_rgb(
+                segment.hue,
+                segment.saturation,
+                segment.luminance * shade(z),
+            );
+            items.push((
+                z,
+                Shape::line_segment([a.0, b.0], (segment.width * 0.5 * (a.2 + b.2), color)),
+            ));
+        }
+
+        if self.setting.show_balls {
+            let first = root.end();
+            let bobs = [
+                first,
+                root.apply(transforms[0]).end(),
+                root.apply(transforms[1]).end(),
+            ];
+            for (i, bob) in bobs.into_iter().enumerate() {
+                let (pos, z, scale) = project(bob);
+                if scale <= 0.0 {
+                    continue;
+                }
+                let decay = i as i32 + 1;
+                items.push((
+                    z,
+                    Shape::circle_filled(
+                        pos,
+                        self.setting.m[i].sqrt() as f32 * self.setting.ball_radius * scale,
+                        hsl_to_rgb(
+                            lerp(h1, h2, 0.5),
+                            self.setting.saturation * self.setting.saturation_decay.powi(decay),
+                            self.setting.luminance
+                                * self.setting.luminance_decay.powi(decay)
+                                * shade(z),
+                        ),
+                    ),
+                ));
+            }
+        }
+
+        // 远的先画
+        items.sort_by(|a, b| b.0.total_cmp(&a.0));
+        items.into_iter().map(|(_, shape)| shape).collect()
+    }
+
+    pub(super) fn spherical_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.setting.spherical, "启用")
+            .on_hover_text("每根臂多一个离开画面的角度φ，只用到质量、长度和重力，其余变体不生效");
+        if !self.setting.spherical {
+            return;
+        }
+
+        egui::Grid::new("球面摆网格")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                for k in 0..3 {
+                    ui.label(format!("φ{}", k + 1));
+                    ui.add(egui::Slider::new(
+                        &mut self.setting.azimuth[2 * k],
+                        -PI..=PI,
+                    ));
+                    ui.end_row();
+                }
+                for k in 0..3 {
+                    ui.label(format!("φ{}'", k + 1));
+                    ui.add(
+                        egui::Slider::new(&mut self.setting.azimuth[2 * k + 1], -10.0..=10.0)
+                            .clamping(egui::SliderClamping::Never),
+                    );
+                    ui.end_row();
+                }
+
+                ui.label("相机距离").on_hover_text("越近透视越强");
+                ui.add(
+                    egui::Slider::new(&mut self.setting.camera_distance, 1.0..=100.0)
+                        .logarithmic(true)
+                        .clamping(egui::SliderClamping::Never),
+                );
+                ui.end_row();
+
+                ui.label("深度明暗").on_hover_text("最远处的线段变暗的比例");
+                ui.add(egui::Slider::new(
+                    &mut self.setting.depth_shading,
+                    0.0..=1.0,
+                ));
+                ui.end_row();
+            });
+
+        ui.horizontal(|ui| {
+            if ui
+                .button("复位视角")
+                .on_hover_text("在画布上拖动鼠标可以旋转视角")
+                .clicked()
+            {
+                self.setting.camera_yaw = 0.0;
+                self.setting.camera_pitch = 0.0;
+            }
+            if ui
+                .button("压平")
+                .on_hover_text("φ全部归零，回到平面运动")
+                .clicked()
+            {
+                self.setting.azimuth = [0.0; 6];
+            }
+        });
+    }
+}
@@ -0,0 +1,178 @@
+use std::f64::consts::TAU;
+
+use nalgebra::DVector;
+use rand::{Rng as _, SeedableRng as _, rngs::StdRng};
+
+use super::{FractalPendulumApp, Ode, State};
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy)]
+pub(super) enum HeatBath {
+    Off,
+    Langevin,
+    Thermostat,
+}
+
+// 噪声用的随机数生成器，种子改变时重新播种
+pub(super) struct ThermostatData {
+    rng: StdRng,
+    seed: u64,
+}
+
+impl Default for ThermostatData {
+    fn default() -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(0),
+            seed: 0,
+        }
+    }
+}
+
+// Box-Muller变换得到标准正态分布
+fn gaussian(rng: &mut StdRng) -> f64 {
+    let u: f64 = 1.0 - rng.random::<f64>();
+    let v: f64 = rng.random();
+    (-2.0 * u.ln()).sqrt() * (TAU * v).cos()
+}
+
+impl Ode {
+    // 速度的Ornstein-Uhlenbeck过程在dt内的精确解，平衡时速度按温度T服从协方差为T·M⁻¹的正态分布
+    fn langevin(&self, y: &mut State, dt: f64, temperature: f64, friction: f64, rng: &mut StdRng) {
+        let n = self.dof();
+        let Some(cholesky) = self.mass_matrix(y).cholesky() else {
+            return;
+        };
+        let decay = (-friction * dt).exp();
+        let amplitude = (temperature * (1.0 - decay * decay)).max(0.0).sqrt();
+
+        // M = LLᵀ，L⁻ᵀξ的协方差正好是M⁻¹
+        let xi = DVector::from_fn(n, |_, _| gaussian(rng));
+        let Some(kick) = cholesky.l().transpose().solve_upper_triangular(&xi) else {
+            return;
+        };
+        for (j, kj) in kick.iter().enumerate() {
+            y[2 * j + 1] = decay * y[2 * j + 1] + amplitude * kj;
+        }
+    }
+
+    // 按比例缩放速度，让机械能以rate的速率向目标靠拢
+    fn rescale(&self, y: &mut State, dt: f64, target: f64, rate: f64) {
+        let (kinetic, potential) = self.energy(y);
+        // 完全静止时无从缩放
+        if kinetic <= f64::EPSILON {
+            return;
+        }
+        let desired = kinetic + (target - kinetic - potential) * (1.0 - (-rate * dt).exp());
+        let scale = (desired.max(0.0) / kinetic).sqrt();
+        for j in 0..self.dof() {
+            y[2 * j + 1] *= scale;
+        }
+    }
+}
+
+impl FractalPendulumApp {
+    // 积分完一帧之后给状态加上热浴的作用
+    pub(super) fn apply_heat_bath(&mut self, ode: &Ode) {
+        let dt = self.setting.delta_t;
+        let mut y = self.setting.state();
+        match self.setting.heat_bath {
+            HeatBath::Off => return,
+            HeatBath::Langevin => {
+                let data = &mut self.data.thermostat;
+                if data.seed != self.setting.noise_seed {
+                    *data = ThermostatData {
+                        rng: StdRng::seed_from_u64(self.setting.noise_seed),
+                        seed: self.setting.noise_seed,
+                    };
+                }
+                ode.langevin(
+                    &mut y,
+                    dt,
+                    self.setting.temperature,
+                    self.setting.friction,
+                    &mut data.rng,
+                );
+            }
+            HeatBath::Thermostat => {
+                ode.rescale(
+                    &mut y,
+                    dt,
+                    self.setting.target_energy,
+                    self.setting.thermostat_rate,
+                );
+            }
+        }
+        self.setting.set_state(&y);
+    }
+
+    pub(super) fn thermostat_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.setting.heat_bath, HeatBath::Off, "关");
+            ui.selectable_value(&mut self.setting.heat_bath, HeatBath::Langevin, "朗之万")
+                .on_hover_text("每帧给速度加上摩擦和随机力，长时间后动能在各自由度上按温度均分");
+            ui.selectable_value(&mut self.setting.heat_bath, HeatBath::Thermostat, "恒能")
+                .on_hover_text("每帧按比例缩放速度，让机械能缓慢回到目标值");
+        });
+
+        match self.setting.heat_bath {
+            HeatBath::Off => {}
+            HeatBath::Langevin => {
+                egui::Grid::new("热浴网格")
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("温度");
+                        ui.add(
+                            egui::Slider::new(&mut self.setting.temperature, 0.0..=10.0)
+                                .clamping(egui::SliderClamping::Never),
+                        );
+                        ui.end_row();
+
+                        ui.label("摩擦");
+                        ui.add(
+                            egui::Slider::new(&mut self.setting.friction, 0.001..=10.0)
+                                .logarithmic(true)
+                                .clamping(egui::SliderClamping::Never),
+                        )
+                        .on_hover_text("速度衰减的速率，越大与热浴耦合越强");
+                        ui.end_row();
+
+                        ui.label("种子");
+                        ui.add(egui::DragValue::new(&mut self.setting.noise_seed));
+                        ui.end_row();
+                    });
+                if ui
+                    .button("重新播种")
+                    .on_hover_text("从当前种子重新开始，同样的初值会得到同样的轨迹")
+                    .clicked()
+                {
+                    self.data.thermostat = ThermostatData {
+                        rng: StdRng::seed_from_u64(self.setting.noise_seed),
+                        seed: self.setting.noise_seed,
+                    };
+                }
+            }
+            HeatBath::Thermostat => {
+                egui::Grid::new("恒能网格")
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("目标能量");
+                        ui.add(egui::DragValue::new(&mut self.setting.target_energy).speed(0.1));
+                        ui.end_row();
+
+                        ui.label("速率");
+                        ui.add(
+                            egui::Slider::new(&mut self.setting.thermostat_rate, 0.001..=10.0)
+                                .logarithmic(true)
+                                .clamping(egui::SliderClamping::Never),
+                        )
+                        .on_hover_text("机械能向目标靠拢的速率，太大时运动会显得生硬");
+                        ui.end_row();
+                    });
+                if ui.button("取当前机械能").clicked() {
+                    self.setting.target_energy = self.data.e;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,522 @@
+use std::sync::Arc;
+
+use egui::{
+    Color32, Mesh, Pos2, Rect, Vec2,
+    epaint::{Vertex, WHITE_UV},
+};
+use num_complex::Complex64;
+
+use super::{CullStats, hsl_to_rgb, lerp};
+
+// 分形一层最多展开的节点数，再多就画不动了
+const MAX_NODES: usize = 1 << 21;
+// 并行细分时每块的线段数
+const TESSELLATE_PIECE: usize = 1 << 14;
+// 前几层在主线程里展开，之后每个节点的子树各算一块，块数固定，结果与线程数无关
+const SPLIT_LEVELS: usize = 6;
+
+// 世界坐标到屏幕的变换，世界坐标原点在画面中心。放大很多倍时f32的坐标会抖，
+// 所以全程用f64，相对画面中心算完再转成f32
+#[derive(Clone, Copy, PartialEq)]
+pub(super) struct View {
+    rect: Rect,
+    scale: f64,
+}
+
+impl View {
+    pub(super) fn new(to_screen: &egui::emath::RectTransform) -> Self {
+        Self {
+            rect: *to_screen.to(),
+            scale: f64::from(to_screen.to().width()) / f64::from(to_screen.from().width()),
+        }
+    }
+
+    pub(super) fn apply(&self, z: Complex64) -> Pos2 {
+        let center = self.rect.center();
+        Pos2::new(
+            center.x + (z.re * self.scale) as f32,
+            center.y + (z.im * self.scale) as f32,
+        )
+    }
+
+    pub(super) fn unapply(&self, p: Pos2) -> Complex64 {
+        let d = p - self.rect.center();
+        Complex64::new(f64::from(d.x), f64::from(d.y)) / self.scale
+    }
+}
+
+// 使用起点+向量的形式保存线段，复数便于表示分形迭代时的关系。
+// 色相按节点在这一层里的位置插值，剪掉的子树不影响其余节点，所以记下占的区间[lo, lo + span)
+#[derive(Clone, Copy, PartialEq)]
+pub(super) struct Node {
+    pub(super) start: Complex64,
+    pub(super) vec: Complex64,
+    lo: f32,
+    span: f32,
+}
+
+impl Node {
+    pub(super) fn root(start: Complex64, vec: Complex64) -> Self {
+        Self {
+            start,
+            vec,
+            lo: 0.0,
+            span: 1.0,
+        }
+    }
+
+    pub(super) fn apply(&self, transform: Complex64, branch: usize) -> Self {
+        Self {
+            start: self.start + self.vec,
+            vec: self.vec * transform,
+            lo: self.lo + branch as f32 * self.span * 0.5,
+            span: self.span * 0.5,
+        }
+    }
+
+    fn hue(&self, h1: f32, h2: f32) -> f32 {
+        lerp(h1, h2, self.lo + self.span * 0.5)
+    }
+}
+
+// 屏幕上的一根线段
+#[derive(Clone, Copy)]
+pub(super) struct Segment {
+    pub(super) line: [Pos2; 2],
+    pub(super) width: f32,
+    pub(super) color: Color32,
+}
+
+// 一块子树展开的结果：每层的线段，以及剪掉的子树
+#[derive(Default)]
+struct Growth {
+    levels: Vec<Vec<Segment>>,
+    frontier: Vec<Node>,
+    cull: CullStats,
+}
+
+// 展开分形需要的全部参数，各线程共用
+pub(super) struct Grower {
+    pub(super) transforms: [Complex64; 2],
+    pub(super) depth: usize,
+    pub(super) view: View,
+    pub(super) hue: [f32; 2],
+    // 根部线段的宽度、亮度和饱和度，以及每层的衰减
+    pub(super) style: [f32; 3],
+    pub(super) decay: [f32; 3],
+    pub(super) threshold: f32,
+}
+
+impl Grower {
+    // 子节点的子树都在以线段终点为圆心、|vec|·r·(1 + r + … + r^(k-1))为半径的圆里，
+    // r为两个缩放比例中较大的，k为剩下的层数
+    pub(super) fn reach(&self) -> Vec<f64> {
+        let ratio = self.transforms[0].norm().max(self.transforms[1].norm());
+        (0..self.depth)
+            .scan(0.0, |sum, k| {
+                *sum += ratio.powi(k as i32 + 1);
+                Some(*sum)
+            })
+            .collect()
+    }
+
+    // 第level层线段的颜色和宽度，最后一层与倒数第二层相同
+    pub(super) fn paint(&self, node: &Node, level: usize) -> Option<Segment> {
+        let n = level.min(self.depth.saturating_sub(1)) as i32 + 1;
+        let [width, luminance, saturation] =
+            std::array::from_fn(|i| self.style[i] * self.decay[i].powi(n));
+        let color = hsl_to_rgb(node.hue(self.hue[0], self.hue[1]), saturation, luminance);
+
+        let a = node.start;
+        let b = node.start + node.vec;
+        let line = [self.view.apply(a), self.view.apply(b)];
+        self.view
+            .rect
+            .intersects(Rect::from_two_pos(line[0], line[1]))
+            .then_some(Segment { line, width, color })
+    }
+
+    // 子树不到细节阈值那么大，或者外接圆整个在画面外时，不再往下分
+    pub(super) fn expand(
+        &self,
+        node: &Node,
+        reach: &[f64],
+        remaining: usize,
+        cull: &mut CullStats,
+    ) -> bool {
+        let end = node.start + node.vec;
+        let radius = node.vec.norm() * reach[remaining - 1] * self.view.scale;
+        let keep = if 2.0 * radius < f64::from(self.threshold) {
+            cull.subpixel += 1;
+            false
+        } else if f64::from(self.view.rect.distance_to_pos(self.view.apply(end))) > radius {
+            cull.offscreen += 1;
+            false
+        } else {
+            true
+        };
+        if !keep {
+            // 渐进渲染可以比递归深度深很多，子树的线段数超出u64时按u64::MAX算
+            let descendants = 1u64
+                .checked_shl(remaining as u32 + 1)
+                .map_or(u64::MAX, |n| n - 2);
+            cull.skipped = cull.skipped.saturating_add(descendants);
+        }
+        keep
+    }
+
+    // 从第from层展开到第to层之前，最后一层或节点太多时画完就停
+    fn grow(&self, mut nodes: Vec<Node>, from: usize, to: usize, budget: usize) -> Growth {
+        let reach = self.reach();
+        let mut growth = Growth::default();
+
+        for level in from..to {
+            // 缩放比例接近1时剪不掉多少，一层的节点太多就提前停下
+            let last = level == self.depth || nodes.len() > budget;
+            let mut segments = Vec::with_capacity(nodes.len());
+            let mut children = Vec::new();
+            for node in &nodes {
+                segments.extend(self.paint(node, level));
+                if !last && self.expand(node, &reach, self.depth - level, &mut growth.cull) {
+                    for (branch, &transform) in self.transforms.iter().enumerate() {
+                        children.push(node.apply(transform, branch));
+                    }
+                }
+            }
+            growth.levels.push(segments);
+            nodes = children;
+            if last {
+                break;
+            }
+        }
+        growth.frontier = nodes;
+        growth
+    }
+
+    // 先在主线程里展开前几层，再把各个子树分开算，最后按层合并，顺序与逐层展开时完全一样
+    pub(super) fn segments(&self, root: Node) -> (Vec<Segment>, CullStats) {
+        let split = SPLIT_LEVELS.min(self.depth + 1);
+        let top = self.grow(vec![root], 0, split, MAX_NODES);
+        let mut cull = top.cull;
+        let mut levels = top.levels;
+
+        // 总数仍按MAX_NODES限制，由剪剩下的子树平分，放大到只剩一支时它能用满全部
+        let budget = MAX_NODES / top.frontier.len().max(1);
+        let growths = parallel_map(top.frontier, |node| {
+            self.grow(vec![node], split, self.depth + 1, budget)
+        });
+        for growth in growths {
+            cull.offscreen += growth.cull.offscreen;
+            cull.subpixel += growth.cull.subpixel;
+            cull.skipped = cull.skipped.saturating_add(growth.cull.skipped);
+            for (i, segments) in growth.levels.into_iter().enumerate() {
+                if levels.len() <= split + i {
+                    levels.push(Vec::new());
+                }
+                levels[split + i].extend(segments);
+            }
+        }
+        (levels.into_iter().flatten().collect(), cull)
+    }
+}
+
+// 原生平台按核数开线程，每个线程隔项分担，结果按原来的顺序返回，与线程数无关
+#[cfg(not(target_arch = "wasm32"))]
+fn parallel_map<T: Send, R: Send>(items: Vec<T>, f: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let workers = std::thread::available_parallelism().map_or(1, usize::from);
+    parallel_map_with(workers, items, f)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn parallel_map_with<T: Send, R: Send>(
+    workers: usize,
+    items: Vec<T>,
+    f: impl Fn(T) -> R + Sync,
+) -> Vec<R> {
+    let workers = workers.min(items.len());
+    if workers <= 1 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let mut shares: Vec<Vec<(usize, T)>> = (0..workers).map(|_| Vec::new()).collect();
+    for (i, item) in items.into_iter().enumerate() {
+        shares[i % workers].push((i, item));
+    }
+    let f = &f;
+    let mut results: Vec<(usize, R)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = shares
+            .into_iter()
+            .map(|share| {
+                scope.spawn(move || {
+                    share
+                        .into_iter()
+                        .map(|(i, item)| (i, f(item)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("画分形的线程不应当出错"))
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+// 网页上没有线程，依次计算
+#[cfg(target_arch = "wasm32")]
+fn parallel_map<T, R>(items: Vec<T>, f: impl Fn(T) -> R) -> Vec<R> {
+    items.into_iter().map(f).collect()
+}
+
+// 上一帧的网格egui用完后只剩这一份引用，清空了接着用，省得每帧重新分配
+pub(super) fn reuse(mesh: &mut Arc<Mesh>) -> &mut Mesh {
+    if Arc::get_mut(mesh).is_none() {
+        *mesh = Arc::default();
+    }
+    let mesh = Arc::get_mut(mesh).expect("新建的网格没有别的引用");
+    mesh.clear();
+    mesh
+}
+
+// 往网格里预留好的一段写顶点和三角形，first是这一段第一个顶点在整个网格里的编号
+struct Writer<'a> {
+    vertices: &'a mut [Vertex],
+    indices: &'a mut [u32],
+    first: u32,
+    vertex_count: usize,
+    index_count: usize,
+}
+
+impl Writer<'_> {
+    // 下一个顶点的编号
+    fn next(&self) -> u32 {
+        self.first + self.vertex_count as u32
+    }
+
+    fn vertex(&mut self, pos: Pos2, color: Color32) -> u32 {
+        let index = self.next();
+        self.vertices[self.vertex_count] = Vertex {
+            pos,
+            uv: WHITE_UV,
+            color,
+        };
+        self.vertex_count += 1;
+        index
+    }
+
+    fn triangle(&mut self, triangle: [u32; 3]) {
+        self.indices[self.index_count..self.index_count + 3].copy_from_slice(&triangle);
+        self.index_count += 3;
+    }
+}
+
+impl Segment {
+    // 横向各列顶点到中线的距离和颜色：两侧各有宽为feather的透明过渡带做抗锯齿，
+    // 不到一个像素宽的线按宽度调淡，中间只要一列顶点
+    fn columns(&self, feather: f32) -> ([(f32, Color32); 4], usize) {
+        let edge = Color32::TRANSPARENT;
+        if self.width > feather {
+            let inner = 0.5 * (self.width - feather);
+            let outer = inner + feather;
+            (
+                [
+                    (outer, edge),
+                    (inner, self.color),
+                    (-inner, self.color),
+                    (-outer, edge),
+                ],
+                4,
+            )
+        } else {
+            let color = self.color.gamma_multiply(self.width / feather);
+            (
+                [(feather, edge), (0.0, color), (-feather, edge), (0.0, edge)],
+                3,
+            )
+        }
+    }
+
+    // 够粗的线段在起点补半个圆盘，和上一根接起来没有缺口，返回半径和分段数
+    fn joint(&self, feather: f32) -> Option<(f32, u32)> {
+        let inner = 0.5 * (self.width - feather);
+        (inner >= 1.0).then(|| {
+            let radius = inner + 0.5 * feather;
+            (radius, (radius.ceil() as u32).clamp(2, 8))
+        })
+    }
+
+    // 占用的顶点数和索引数
+    fn footprint(&self, feather: f32) -> (usize, usize) {
+        let (_, n) = self.columns(feather);
+        let (vertices, triangles) = self
+            .joint(feather)
+            .map_or((0, 0), |(_, steps)| (steps as usize + 2, steps as usize));
+        (2 * n + vertices, 3 * (2 * (n - 1) + triangles))
+    }
+
+    fn write(&self, feather: f32, out: &mut Writer<'_>) {
+        let [a, b] = self.line;
+        let direction = (b - a).normalized();
+        let normal = direction.rot90();
+        let (columns, n) = self.columns(feather);
+        let columns = &columns[..n];
+
+        let first = out.next();
+        for point in [a, b] {
+            for &(offset, color) in columns {
+                out.vertex(point + normal * offset, color);
+            }
+        }
+        let n = n as u32;
+        for i in first..first + n - 1 {
+            out.triangle([i, i + 1, i + n]);
+            out.triangle([i + 1, i + n + 1, i + n]);
+        }
+
+        if let Some((radius, steps)) = self.joint(feather) {
+            let color = columns[1].1;
+            let center = out.vertex(a, color);
+            for k in 0..=steps {
+                let angle = std::f32::consts::PI * (0.5 + k as f32 / steps as f32);
+                let rotated = Vec2::angled(angle);
+                let offset = direction * rotated.x + normal * rotated.y;
+                out.vertex(a + offset * radius, color);
+            }
+            for k in 1..=steps {
+                out.triangle([center, center + k, center + k + 1]);
+            }
+        }
+    }
+}
+
+// 把线段直接拼成一个网格，免得egui逐个细分几百万个形状，先画深的层，颜色深的在底下。
+// 先数出每一段要多少顶点，在网格里预留好，再分块并行写进各自的位置，结果与逐根写入完全一样
+pub(super) fn tessellate(mesh: &mut Mesh, segments: &[Segment], feather: f32) {
+    let pieces: Vec<&[Segment]> = segments.rchunks(TESSELLATE_PIECE).collect();
+    let sizes = parallel_map(pieces.clone(), |piece| {
+        piece.iter().fold((0, 0), |(v, i), segment| {
+            let (dv, di) = segment.footprint(feather);
+            (v + dv, i + di)
+        })
+    });
+    let (vertex_count, index_count) = sizes
+        .iter()
+        .fold((0, 0), |(v, i), &(dv, di)| (v + dv, i + di));
+
+    let mut base = mesh.vertices.len();
+    let start = mesh.indices.len();
+    mesh.vertices.resize(base + vertex_count, Vertex::default());
+    mesh.indices.resize(start + index_count, 0);
+    let mut vertices = &mut mesh.vertices[base..];
+    let mut indices = &mut mesh.indices[start..];
+
+    let mut jobs = Vec::with_capacity(pieces.len());
+    for (piece, (v, i)) in pieces.into_iter().zip(sizes) {
+        let (piece_vertices, rest) = std::mem::take(&mut vertices).split_at_mut(v);
+        vertices = rest;
+        let (piece_indices, rest) = std::mem::take(&mut indices).split_at_mut(i);
+        indices = rest;
+        jobs.push((
+            piece,
+            Writer {
+                vertices: piece_vertices,
+                indices: piece_indices,
+                first: base as u32,
+                vertex_count: 0,
+                index_count: 0,
+            },
+        ));
+        base += v;
+    }
+    parallel_map(jobs, |(piece, mut out)| {
+        for segment in piece.iter().rev() {
+            segment.write(feather, &mut out);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(scale: f32) -> View {
+        let from = Rect::from_center_size(Pos2::ZERO, Vec2::splat(2.0 / scale));
+        let to = Rect::from_min_size(Pos2::new(10.0, 20.0), Vec2::splat(2.0));
+        View::new(&egui::emath::RectTransform::from_to(from, to))
+    }
+
+    fn grower(transforms: [Complex64; 2], depth: usize) -> Grower {
+        Grower {
+            transforms,
+            depth,
+            view: view(1.0),
+            hue: [0.0, 1.0],
+            style: [1.0; 3],
+            decay: [1.0; 3],
+            threshold: 0.0,
+        }
+    }
+
+    #[test]
+    fn view_round_trip() {
+        for scale in [1.0, 1e3, 1e8] {
+            let view = view(scale);
+            // 离画面中心不远的点都能准确还原
+            for (x, y) in [(0.0, 0.0), (0.3, -0.7), (-0.9, 0.5)] {
+                let z = Complex64::new(x, y) / f64::from(scale);
+                let back = view.unapply(view.apply(z));
+                assert!(
+                    (back - z).norm() <= 1e-6 * z.norm().max(1.0 / f64::from(scale)),
+                    "缩放{scale}时{z}还原成了{back}"
+                );
+            }
+            // 屏幕上的点反过来也一样
+            let p = Pos2::new(10.25, 21.75);
+            assert!((view.apply(view.unapply(p)) - p).length() < 1e-4);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn parallel_map_keeps_order() {
+        let items: Vec<u64> = (0..1000).collect();
+        let expected: Vec<u64> = items.iter().map(|i| i * i + 7).collect();
+        for workers in [1, 2, 3, 8, 2000] {
+            assert_eq!(
+                parallel_map_with(workers, items.clone(), |i| i * i + 7),
+                expected,
+                "{workers}个线程时顺序不对"
+            );
+        }
+    }
+
+    #[test]
+    fn reach_bounds_every_descendant() {
+        for transforms in [
+            [Complex64::new(0.5, 0.3), Complex64::new(-0.2, 0.6)],
+            [Complex64::new(0.9, 0.1), Complex64::new(0.0, -0.95)],
+            [Complex64::new(1.1, 0.0), Complex64::new(0.3, 0.4)],
+        ] {
+            let depth = 10;
+            let grower = grower(transforms, depth);
+            let reach = grower.reach();
+            let root = Node::root(Complex64::new(0.3, -0.2), Complex64::new(0.0, 1.0));
+            let end = root.start + root.vec;
+            let radius = root.vec.norm() * reach[depth - 1];
+
+            let mut nodes = vec![root];
+            for _ in 0..depth {
+                nodes = nodes
+                    .iter()
+                    .flat_map(|node| [node.apply(transforms[0], 0), node.apply(transforms[1], 1)])
+                    .collect();
+                for node in &nodes {
+                    let distance = (node.start + node.vec - end).norm();
+                    assert!(distance <= radius * (1.0 + 1e-12), "{distance} > {radius}");
+                }
+            }
+        }
+    }
+}